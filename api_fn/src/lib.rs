@@ -0,0 +1,41 @@
+use lib::{
+    base_url::BaseUrl,
+    cors::CorsConfig,
+    error::Error as LibError,
+    helpers::{prefers_json, ResponseBuilder},
+    model::AuthContext,
+    router::{HandlerResult, Route, RouteRequest, Router},
+    templates::{render, Branding, LandingPage},
+};
+
+/// The protected API, behind the `auth_fn` authorizer
+pub fn router() -> Router<()> {
+    Router::new(())
+        .base_url(BaseUrl::from_env())
+        .cors(CorsConfig::from_env())
+        .route(Route::get("/protected", protected))
+}
+
+/// The signed in user, as a page for browsers or as JSON for `Accept: application/json`
+async fn protected(req: RouteRequest<()>) -> HandlerResult {
+    let identity = req
+        .request
+        .request_context
+        .authorizer
+        .as_ref()
+        .and_then(|auth_ctx| AuthContext::from_lambda(&auth_ctx.lambda))
+        .ok_or(LibError::Forbidden(String::from(
+            "You need to sign in to see this page.",
+        )))?;
+
+    if prefers_json(req.header("accept")) {
+        return ResponseBuilder::ok().json(&identity).build();
+    }
+
+    let page = render(&LandingPage {
+        branding: Branding::global(),
+        email: &identity.email,
+        logout_url: Some(req.url("/logout")?),
+    })?;
+    ResponseBuilder::ok().html(page).build()
+}
@@ -0,0 +1,74 @@
+use async_session::SessionStore;
+use aws_lambda_events::apigw::{
+    ApiGatewayV2CustomAuthorizerSimpleResponse as Response,
+    ApiGatewayV2CustomAuthorizerV2Request as Request,
+};
+use lambda_runtime::{Error, LambdaEvent};
+use lib::{
+    helpers::find_cookie,
+    model::{AuthContext, User, COOKIE_NAME},
+    tokens::TokenVault,
+};
+use serde_json::json;
+
+/// Authorize a request by the session in its `SESSION` cookie. A session that follows a
+/// provider grant ends once the grant's token is gone from `tokens`, which costs a table
+/// read per request unless `tokens` caches found grants, see `TokenVault::with_grant_cache`.
+pub async fn function_handler(
+    event: LambdaEvent<Request>,
+    session_store: &impl SessionStore,
+    tokens: &TokenVault,
+) -> Result<Response, Error> {
+    let Some(cookie) = find_cookie(&event.payload.cookies, COOKIE_NAME) else {
+        return reject();
+    };
+    let Ok(Some(session)) = session_store.load_session(cookie.to_string()) .await else {
+        return reject();
+    };
+    let Some(user) = session.get::<User>("user") else {
+        return reject();
+    };
+    if session.is_expired() {
+        if let Err(e) = session_store.destroy_session(session).await {
+            tracing::error!("failed to destroy session error: {}", e)
+        }
+        return reject();
+    }
+    if let Some(grant) = user.grant.as_ref().filter(|_| tokens.is_enabled()) {
+        match tokens.has(&user.id, grant).await {
+            Ok(true) => {}
+            Ok(false) => {
+                tracing::info!("the grant of `{}` is gone, ending the session", grant);
+                if let Err(e) = session_store.destroy_session(session).await {
+                    tracing::error!("failed to destroy session error: {}", e)
+                }
+                return reject();
+            }
+            Err(e) => {
+                tracing::error!("failed to look up grant error: {}", e);
+                return reject();
+            }
+        }
+    }
+    accept(AuthContext {
+        user_id: user.id,
+        email: user.email,
+        orgs: user.orgs.join(","),
+        teams: user.teams.join(","),
+        scopes: user.scopes.to_string(),
+    })
+}
+
+fn accept(context: AuthContext) -> Result<Response, Error> {
+    Ok(Response {
+        is_authorized: true,
+        context: serde_json::to_value(context)?,
+    })
+}
+
+fn reject() -> Result<Response, Error> {
+    Ok(Response {
+        is_authorized: false,
+        context: json!({}),
+    })
+}
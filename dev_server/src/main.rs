@@ -0,0 +1,431 @@
+//! Local stand in for API Gateway: serves `login_fn`, `api_fn` and `auth_fn` behind one
+//! listener, running the authorizer in front of protected routes the way HttpApi does.
+//!
+//! ```bash
+//! GITHUB_CLIENT_ID=... GITHUB_CLIENT_SECRET=... cargo run
+//! ```
+//!
+//! Sessions live in memory unless `SESSION_STORE=sqlite`, which keeps them in
+//! `SQLITE_PATH` (default `sessions.db`) across restarts, or `SESSION_STORE=dynamo`, which
+//! uses `TABLE_NAME` and optionally `DYNAMODB_ENDPOINT` for DynamoDB Local, or
+//! `SESSION_STORE=redis`, which connects to `REDIS_URL`. Users are kept in the DynamoDB
+//! table with `SESSION_STORE=dynamo` and in memory otherwise.
+
+use std::{collections::HashMap, convert::Infallible, net::SocketAddr, sync::Arc};
+
+use async_session::SessionStore;
+use aws_lambda_events::{
+    apigw::{
+        ApiGatewayV2CustomAuthorizerV2Request as AuthorizerRequest,
+        ApiGatewayV2httpRequest as Request, ApiGatewayV2httpRequestContextAuthorizerDescription,
+        ApiGatewayV2httpResponse as Response,
+    },
+    encodings::Body as EventBody,
+    http::{header::SET_COOKIE, HeaderValue, Method},
+    query_map::QueryMap,
+};
+use hyper::{
+    service::{make_service_fn, service_fn},
+    Body, Server,
+};
+use lambda_runtime::{Context, Error, LambdaEvent};
+use lib::{
+    aws::dynamodb::DbClient,
+    envelope::{Envelope, KeyProvider},
+    error::Error as LibError, helpers::ResponseBuilder, oauth::github::GithubProvider,
+    router::Router,
+    secrets::SecretProvider,
+    signin::SignInPolicy,
+    session::{DynamoSessionStore, MemorySessionStore, RedisSessionStore, SqliteSessionStore},
+    tokens::TokenVault,
+    users::{DynamoUserStore, MemoryUserStore, UserStore},
+};
+use login_fn::State;
+
+const CLIENT_ID_PARAM: &str = "/oath/dev/oauth/github/client_id";
+const CLIENT_SECRET_PARAM: &str = "/oath/dev/oauth/github/client_secret";
+
+struct App<S, U> {
+    login: Router<State<S, U>>,
+    api: Router<()>,
+    session_store: S,
+    tokens: TokenVault,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_target(false)
+        .init();
+
+    let port = std::env::var("PORT")
+        .ok()
+        .and_then(|port| port.parse::<u16>().ok())
+        .unwrap_or(3000);
+    set_default_env("PUBLIC_BASE_URL", &format!("http://localhost:{port}"));
+    // HSTS on localhost would stick to every other local project
+    set_default_env("SECURITY_HSTS", "off");
+    set_default_env("PARAM_GITHUB_CLIENT_ID", CLIENT_ID_PARAM);
+    set_default_env("PARAM_GITHUB_CLIENT_SECRET", CLIENT_SECRET_PARAM);
+
+    let secrets = match (
+        std::env::var("GITHUB_CLIENT_ID"),
+        std::env::var("GITHUB_CLIENT_SECRET"),
+    ) {
+        (Ok(client_id), Ok(client_secret)) => SecretProvider::Static(HashMap::from([
+            (std::env::var("PARAM_GITHUB_CLIENT_ID")?, client_id),
+            (std::env::var("PARAM_GITHUB_CLIENT_SECRET")?, client_secret),
+        ])),
+        _ => {
+            tracing::info!("GITHUB_CLIENT_ID/GITHUB_CLIENT_SECRET not set, using SSM");
+            SecretProvider::ssm().await
+        }
+    };
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+    match std::env::var("SESSION_STORE").as_deref() {
+        Ok("dynamo") => {
+            let Ok(table_name) = std::env::var("TABLE_NAME") else {
+                return Err(LibError::config("ENV VAR TABLE_NAME not set").into());
+            };
+            let db_client = DbClient::new(&table_name).await;
+            let users = DynamoUserStore::new(db_client.clone());
+            let mut store = DynamoSessionStore::new(db_client).await;
+            if let Some(provider) = KeyProvider::from_env().await? {
+                store = store.with_encryption(Envelope::new(provider));
+            }
+            serve(addr, secrets, store, users).await
+        }
+        Ok("redis") => {
+            let url = std::env::var("REDIS_URL").unwrap_or(String::from("redis://127.0.0.1:6379"));
+            let store = RedisSessionStore::new(&url).await?;
+            serve(addr, secrets, store, MemoryUserStore::new()).await
+        }
+        Ok("sqlite") => {
+            let path = std::env::var("SQLITE_PATH").unwrap_or(String::from("sessions.db"));
+            let store = SqliteSessionStore::open(&path)?;
+            serve(addr, secrets, store, MemoryUserStore::new()).await
+        }
+        _ => serve(addr, secrets, MemorySessionStore::new(), MemoryUserStore::new()).await,
+    }
+}
+
+fn set_default_env(name: &str, value: &str) {
+    if std::env::var_os(name).is_none() {
+        std::env::set_var(name, value);
+    }
+}
+
+async fn serve<S: SessionStore, U: UserStore>(
+    addr: SocketAddr,
+    secrets: SecretProvider,
+    session_store: S,
+    user_store: U,
+) -> Result<(), Error> {
+    let rest_client = reqwest::Client::builder().user_agent("oath").build()?;
+    // tokens stay in memory unless TOKEN_ENCRYPTION configures the vault
+    let tokens = match TokenVault::from_env().await? {
+        tokens if tokens.is_enabled() => tokens,
+        _ => TokenVault::memory(),
+    };
+    let app = Arc::new(App {
+        login: login_fn::router(State {
+            providers: GithubProvider::all_from_env(rest_client)?,
+            secrets,
+            session_store: session_store.clone(),
+            user_store,
+            sign_in: SignInPolicy::from_env().await?,
+            tokens: tokens.clone(),
+        }),
+        api: api_fn::router(),
+        session_store,
+        tokens,
+    });
+
+    let make_svc = make_service_fn(move |_| {
+        let app = app.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let app = app.clone();
+                async move { Ok::<_, Infallible>(app.handle(req).await) }
+            }))
+        }
+    });
+
+    tracing::info!("listening on http://{addr}, sign in at http://{addr}/login");
+    Server::bind(&addr).serve(make_svc).await?;
+    Ok(())
+}
+
+impl<S: SessionStore, U: UserStore> App<S, U> {
+    async fn handle(&self, req: hyper::Request<Body>) -> hyper::Response<Body> {
+        let event = match to_event(req).await {
+            Ok(event) => event,
+            Err(err) => {
+                tracing::error!("failed to read request {}", err);
+                return to_hyper(text(400, "Bad Request"));
+            }
+        };
+        let method = event.request_context.http.method.clone();
+        let path = event.raw_path.clone().unwrap_or_default();
+        tracing::info!("{} {}", method, path);
+
+        // mirrors the `Auth: NONE` routes in template.yaml
+        let res = if path == "/login"
+            || path == "/logout"
+            || path.starts_with("/login/")
+            || path.starts_with("/account/")
+        {
+            self.login.handle(lambda_event(event)).await
+        } else if method == Method::OPTIONS && path == "/protected" {
+            self.api.handle(lambda_event(event)).await
+        } else {
+            match self.authorize(event).await {
+                Some(event) => self.api.handle(lambda_event(event)).await,
+                None => ResponseBuilder::new(403)
+                    .json(&serde_json::json!({ "message": "Forbidden" }))
+                    .build()
+                    .expect("static response is valid"),
+            }
+        };
+        to_hyper(res)
+    }
+
+    /// Run `auth_fn` and inject its context like HttpApi's simple responses do
+    async fn authorize(&self, mut event: Request) -> Option<Request> {
+        let auth_request = AuthorizerRequest {
+            version: Some(String::from("2.0")),
+            type_: Some(String::from("REQUEST")),
+            raw_path: event.raw_path.clone(),
+            raw_query_string: event.raw_query_string.clone(),
+            cookies: event.cookies.clone().unwrap_or_default(),
+            headers: event.headers.clone(),
+            query_string_parameters: event
+                .query_string_parameters
+                .iter()
+                .map(|(key, value)| (key.to_owned(), value.to_owned()))
+                .collect(),
+            request_context: event.request_context.clone(),
+            ..Default::default()
+        };
+
+        let res = auth_fn::function_handler(
+            lambda_event(auth_request),
+            &self.session_store,
+            &self.tokens,
+        )
+        .await
+            .map_err(|err| tracing::error!("authorizer failed {}", err))
+            .ok()?;
+        if !res.is_authorized {
+            tracing::info!("authorizer rejected the request");
+            return None;
+        }
+
+        let lambda = match res.context {
+            serde_json::Value::Object(context) => context.into_iter().collect(),
+            _ => HashMap::new(),
+        };
+        event.request_context.authorizer =
+            Some(ApiGatewayV2httpRequestContextAuthorizerDescription {
+                jwt: None,
+                lambda,
+                iam: None,
+            });
+        Some(event)
+    }
+}
+
+fn lambda_event<T>(payload: T) -> LambdaEvent<T> {
+    LambdaEvent::new(payload, Context::default())
+}
+
+/// Translate a plain HTTP request into an HttpApi payload v2 event on the `$default` stage
+async fn to_event(req: hyper::Request<Body>) -> Result<Request, Error> {
+    let (parts, body) = req.into_parts();
+    let body = hyper::body::to_bytes(body).await?;
+
+    let raw_query_string = parts.uri.query().unwrap_or_default().to_owned();
+    let mut query = HashMap::<String, Vec<String>>::new();
+    for (key, value) in serde_urlencoded::from_str::<Vec<(String, String)>>(&raw_query_string)? {
+        query.entry(key).or_default().push(value);
+    }
+    let cookies = parts
+        .headers
+        .get_all("cookie")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .map(|cookie| cookie.trim().to_owned())
+        .filter(|cookie| !cookie.is_empty())
+        .collect::<Vec<_>>();
+
+    let mut event = Request {
+        version: Some(String::from("2.0")),
+        raw_path: Some(parts.uri.path().to_owned()),
+        raw_query_string: Some(raw_query_string),
+        cookies: Some(cookies),
+        headers: parts.headers,
+        query_string_parameters: QueryMap::from(query),
+        body: match body.is_empty() {
+            true => None,
+            false => Some(String::from_utf8_lossy(&body).into_owned()),
+        },
+        ..Default::default()
+    };
+    let context = &mut event.request_context;
+    context.stage = Some(String::from("$default"));
+    context.domain_name = event
+        .headers
+        .get("host")
+        .and_then(|host| host.to_str().ok())
+        .map(String::from);
+    context.http.method = parts.method;
+    context.http.path = event.raw_path.clone();
+    Ok(event)
+}
+
+fn to_hyper(res: Response) -> hyper::Response<Body> {
+    let mut builder = hyper::Response::builder().status(res.status_code as u16);
+    if let Some(headers) = builder.headers_mut() {
+        headers.extend(res.headers);
+        for cookie in &res.cookies {
+            match HeaderValue::from_str(cookie) {
+                Ok(cookie) => {
+                    headers.append(SET_COOKIE, cookie);
+                }
+                Err(err) => tracing::error!("invalid cookie {}", err),
+            }
+        }
+    }
+    let body = match res.body {
+        Some(EventBody::Text(text)) => Body::from(text),
+        Some(EventBody::Binary(bytes)) => Body::from(bytes),
+        Some(EventBody::Empty) | None => Body::empty(),
+    };
+    builder.body(body).unwrap_or_else(|err| {
+        tracing::error!("failed to build response {}", err);
+        hyper::Response::new(Body::empty())
+    })
+}
+
+fn text(status: u16, body: &str) -> Response {
+    ResponseBuilder::new(status)
+        .text(body)
+        .build()
+        .expect("static response is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_session::Session;
+    use lib::{model::User, tokens::ProviderToken};
+
+    async fn app() -> App<MemorySessionStore, MemoryUserStore> {
+        let session_store = MemorySessionStore::new();
+        let tokens = TokenVault::memory();
+        App {
+            login: login_fn::router(State {
+                providers: vec![GithubProvider::github_com(
+                    "github",
+                    reqwest::Client::new(),
+                    CLIENT_ID_PARAM,
+                    CLIENT_SECRET_PARAM,
+                )],
+                secrets: SecretProvider::Static(HashMap::new()),
+                session_store: session_store.clone(),
+                user_store: MemoryUserStore::new(),
+                sign_in: SignInPolicy::default(),
+                tokens: tokens.clone(),
+            }),
+            api: api_fn::router(),
+            session_store,
+            tokens,
+        }
+    }
+
+    fn get(path: &str, cookie: Option<&str>) -> hyper::Request<Body> {
+        let mut req = hyper::Request::get(path)
+            .header("host", "localhost:3000")
+            .header("accept", "application/json");
+        if let Some(cookie) = cookie {
+            req = req.header("cookie", format!("theme=dark; OLD_SESSION=x; SESSION={cookie}"));
+        }
+        req.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn runs_the_authorizer_before_protected_routes() {
+        let app = app().await;
+        let res = app.handle(get("/protected", None)).await;
+        assert_eq!(res.status(), 403);
+
+        let mut session = Session::new();
+        session
+            .insert(
+                "user",
+                User {
+                    id: String::from("dev"),
+                    email: String::from("dev@example.com"),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let cookie = app.session_store.store_session(session).await.unwrap().unwrap();
+
+        let res = app.handle(get("/protected", Some(&cookie))).await;
+        assert_eq!(res.status(), 200);
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let identity: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(identity["user_id"], "dev");
+        assert_eq!(identity["email"], "dev@example.com");
+    }
+
+    #[tokio::test]
+    async fn ends_sessions_whose_grant_is_gone() {
+        let app = app().await;
+        let token = ProviderToken {
+            provider: String::from("github"),
+            subject: String::from("1234"),
+            access_token: String::from("ghu_1"),
+            refresh_token: Some(String::from("ghr_1")),
+            expires_at: None,
+            scopes: Default::default(),
+        };
+        app.tokens.put("dev", &token).await.unwrap();
+
+        let mut session = Session::new();
+        session
+            .insert(
+                "user",
+                User {
+                    id: String::from("dev"),
+                    email: String::from("dev@example.com"),
+                    grant: Some(token.identity()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let cookie = app.session_store.store_session(session).await.unwrap().unwrap();
+        let res = app.handle(get("/protected", Some(&cookie))).await;
+        assert_eq!(res.status(), 200);
+
+        // as a refused refresh does
+        app.tokens.delete("dev", &token.identity()).await.unwrap();
+        let res = app.handle(get("/protected", Some(&cookie))).await;
+        assert_eq!(res.status(), 403);
+        assert!(app.session_store.load_session(cookie).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn serves_login_routes_without_the_authorizer() {
+        let res = app().await.handle(get("/login", None)).await;
+        assert_eq!(res.status(), 200);
+
+        // login_fn checks the session of account routes itself
+        let res = app().await.handle(get("/account/identities", None)).await;
+        assert_eq!(res.status(), 401);
+    }
+}
@@ -1,3 +1,4 @@
+use aws_sdk_dynamodb::error::SdkError;
 use aws_sdk_dynamodb::Client;
 use aws_sdk_dynamodb::operation::delete_item::DeleteItemOutput;
 use aws_sdk_dynamodb::operation::put_item::PutItemOutput;
@@ -9,6 +10,8 @@ use serde_dynamo::from_items;
 use std::collections::HashMap;
 use std::sync::Arc;
 
+use crate::error::Result;
+
 #[derive(Debug)]
 pub struct DbClient {
     table_name: String,
@@ -16,11 +19,16 @@ pub struct DbClient {
 }
 
 impl DbClient {
+    /// `DYNAMODB_ENDPOINT` points the client at e.g. DynamoDB Local
     pub async fn new(table_name: &str) -> Arc<Self> {
         let shared_config = aws_config::load_from_env().await;
+        let mut config = aws_sdk_dynamodb::config::Builder::from(&shared_config);
+        if let Ok(endpoint) = std::env::var("DYNAMODB_ENDPOINT") {
+            config = config.endpoint_url(endpoint);
+        }
 
         Arc::new(Self {
-            inner: Client::new(&shared_config),
+            inner: Client::from_conf(config.build()),
             table_name: table_name.to_string(),
         })
     }
@@ -28,27 +36,47 @@ impl DbClient {
     pub async fn put(
         &self,
         item: impl Serialize + std::fmt::Debug,
-    ) -> anyhow::Result<PutItemOutput> {
+    ) -> Result<PutItemOutput> {
         let item = to_item(item)?;
-        self.inner
+        Ok(self
+            .inner
             .put_item()
             .table_name(&self.table_name)
             .set_item(Some(item))
             .send()
-            .await
-            .map_err(|err| anyhow::anyhow!(err))
+            .await?)
     }
 
-    pub async fn delete(&self, pk: String, sk: String) -> anyhow::Result<DeleteItemOutput> {
-        self.inner
+    /// Put `item` unless an item with its key exists, `false` if one did
+    pub async fn put_if_absent(&self, item: impl Serialize + std::fmt::Debug) -> Result<bool> {
+        let item = to_item(item)?;
+        let res = self
+            .inner
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(item))
+            .condition_expression("attribute_not_exists(PK)")
+            .send()
+            .await;
+        match res {
+            Ok(_) => Ok(true),
+            Err(SdkError::ServiceError(err)) if err.err().is_conditional_check_failed_exception() => {
+                Ok(false)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub async fn delete(&self, pk: String, sk: String) -> Result<DeleteItemOutput> {
+        Ok(self
+            .inner
             .delete_item()
             .table_name(&self.table_name)
             .key("PK", to_attribute_value(pk)?)
             .key("SK", to_attribute_value(sk)?)
             .return_values(aws_sdk_dynamodb::types::ReturnValue::AllOld)
             .send()
-            .await
-            .map_err(|err| anyhow::anyhow!(err))
+            .await?)
     }
 
     pub async fn query<T>(
@@ -57,7 +85,7 @@ impl DbClient {
         expression_attribute_names: HashMap<String, String>,
         expression_attribute_values: HashMap<String, AttributeValue>,
         gsi: Option<String>,
-    ) -> anyhow::Result<Vec<T>>
+    ) -> Result<Vec<T>>
     where
         T: DeserializeOwned,
     {
@@ -82,7 +110,7 @@ impl DbClient {
         pk: String,
         sk: Option<String>,
         gsi: Option<String>,
-    ) -> anyhow::Result<Vec<T>>
+    ) -> Result<Vec<T>>
     where
         T: DeserializeOwned,
     {
@@ -122,15 +150,14 @@ impl DbClient {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde_dynamo::aws_sdk_dynamodb_0_19::to_attribute_value;
-    use serde_json::{json, to_string};
-    use uuid::Uuid;
+    use serde_dynamo::aws_sdk_dynamodb_0_28::to_attribute_value;
 
     #[tokio::test]
+    #[ignore = "requires AWS credentials and the dev table"]
     async fn pk() {
         let client = DbClient::new("oath-db-dev").await;
         let res = client
-            .query::<Entity>(
+            .query::<serde_json::Value>(
                 "#pk = :pk",
                 HashMap::from([(String::from("#pk"), String::from("PK"))]),
                 HashMap::from([(
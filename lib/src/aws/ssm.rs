@@ -13,6 +13,7 @@ mod tests {
     use oauth2::{CsrfToken, Scope};
 
     #[tokio::test]
+    #[ignore = "requires AWS credentials and the dev parameters"]
     async fn get_multi_values() {
         let client = create_client().await;
 
@@ -27,6 +28,7 @@ mod tests {
         let _ = res.parameters().unwrap();
     }
     #[tokio::test]
+    #[ignore = "requires AWS credentials and the dev parameters"]
     async fn test_oauth_client() {
         let client = create_client().await;
 
@@ -49,7 +51,8 @@ mod tests {
             GITHUB_AUTH_URL.to_string(),
             GITHUB_TOKEN_URL.to_string(),
             String::from("http://localhost:3000/login/github/callback"),
-        );
+        )
+        .unwrap();
 
         let (auth_url, _csrf_token) = oc
             .authorize_url(CsrfToken::new_random)
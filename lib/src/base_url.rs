@@ -0,0 +1,139 @@
+use aws_lambda_events::apigw::ApiGatewayV2httpRequest as Request;
+
+use crate::error::Error;
+
+/// Where the public URL of the API comes from.
+///
+/// The client supplied `Host` header is never used, it allows host header injection
+/// and is wrong behind custom domains and CloudFront.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BaseUrl {
+    /// A configured URL such as `https://auth.example.com` or `https://example.com/auth`
+    Fixed(String),
+    /// `X-Forwarded-Host` and `X-Forwarded-Proto`, only when a proxy we control sets them
+    TrustedProxy,
+    /// The domain name and stage API Gateway puts in the request context
+    RequestContext,
+}
+
+impl BaseUrl {
+    /// `PUBLIC_BASE_URL` if set, the forwarded headers if `TRUST_FORWARDED_HEADERS=true`,
+    /// otherwise the API Gateway request context
+    pub fn from_env() -> Self {
+        if let Ok(url) = std::env::var("PUBLIC_BASE_URL") {
+            if !url.trim().is_empty() {
+                return BaseUrl::Fixed(url.trim().trim_end_matches('/').to_owned());
+            }
+        }
+        match std::env::var("TRUST_FORWARDED_HEADERS") {
+            Ok(value) if value.eq_ignore_ascii_case("true") => BaseUrl::TrustedProxy,
+            _ => BaseUrl::RequestContext,
+        }
+    }
+
+    /// The absolute URL of the API root for `request`, without a trailing slash
+    pub fn resolve(&self, request: &Request) -> Result<String, Error> {
+        match self {
+            BaseUrl::Fixed(url) => Ok(url.clone()),
+            BaseUrl::TrustedProxy => {
+                let host = forwarded(request, "x-forwarded-host")
+                    .filter(|host| is_valid_host(host))
+                    .ok_or(Error::BadRequest(String::from(
+                        "The request has no valid `X-Forwarded-Host` header.",
+                    )))?;
+                let proto = match forwarded(request, "x-forwarded-proto") {
+                    Some("http") => "http",
+                    _ => "https",
+                };
+                Ok(format!("{proto}://{host}"))
+            }
+            BaseUrl::RequestContext => {
+                let context = &request.request_context;
+                let domain = context
+                    .domain_name
+                    .as_deref()
+                    .filter(|host| is_valid_host(host))
+                    .ok_or(Error::config("request context has no domain name"))?;
+                match context.stage.as_deref() {
+                    None | Some("$default") => Ok(format!("https://{domain}")),
+                    Some(stage) => Ok(format!("https://{domain}/{stage}")),
+                }
+            }
+        }
+    }
+}
+
+/// The first value of a forwarded header, the one the outermost proxy saw
+fn forwarded<'a>(request: &'a Request, name: &str) -> Option<&'a str> {
+    request
+        .headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next())
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+fn is_valid_host(host: &str) -> bool {
+    !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_lambda_events::http::HeaderValue;
+
+    fn request() -> Request {
+        let mut request = Request::default();
+        request.request_context.domain_name = Some(String::from("abc.execute-api.test"));
+        request.request_context.stage = Some(String::from("Prod"));
+        request
+            .headers
+            .insert("host", HeaderValue::from_static("evil.example.com"));
+        request
+    }
+
+    #[test]
+    fn resolves_from_request_context() {
+        assert_eq!(
+            BaseUrl::RequestContext.resolve(&request()).unwrap(),
+            "https://abc.execute-api.test/Prod"
+        );
+        let mut request = request();
+        request.request_context.stage = Some(String::from("$default"));
+        assert_eq!(
+            BaseUrl::RequestContext.resolve(&request).unwrap(),
+            "https://abc.execute-api.test"
+        );
+    }
+
+    #[test]
+    fn resolves_from_forwarded_headers() {
+        let mut request = request();
+        assert!(BaseUrl::TrustedProxy.resolve(&request).is_err());
+
+        request.headers.insert(
+            "x-forwarded-host",
+            HeaderValue::from_static("auth.example.com, internal.example.com"),
+        );
+        assert_eq!(
+            BaseUrl::TrustedProxy.resolve(&request).unwrap(),
+            "https://auth.example.com"
+        );
+
+        request.headers.insert(
+            "x-forwarded-host",
+            HeaderValue::from_static("auth.example.com/evil?"),
+        );
+        assert!(BaseUrl::TrustedProxy.resolve(&request).is_err());
+    }
+
+    #[test]
+    fn fixed_wins() {
+        let base = BaseUrl::Fixed(String::from("https://example.com/auth"));
+        assert_eq!(base.resolve(&request()).unwrap(), "https://example.com/auth");
+    }
+}
@@ -0,0 +1,296 @@
+use std::time::Duration;
+
+use aws_lambda_events::{
+    apigw::{ApiGatewayV2httpRequest as Request, ApiGatewayV2httpResponse as Response},
+    http::{
+        header::{
+            ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+            ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE,
+            ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+        },
+        HeaderValue, Method,
+    },
+};
+
+use crate::helpers::response;
+
+/// Cross origin policy for browser clients served from another origin
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    /// Exact origins like `https://app.example.com`, `*` allows any origin
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<Method>,
+    pub allowed_headers: Vec<String>,
+    /// Never sent when `allowed_origins` has `*`, or any site could read the API as the user
+    pub allow_credentials: bool,
+    pub max_age: Option<Duration>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec![],
+            allowed_methods: vec![Method::GET],
+            allowed_headers: vec![String::from("accept"), String::from("content-type")],
+            allow_credentials: true,
+            max_age: Some(Duration::from_secs(600)),
+        }
+    }
+}
+
+impl CorsConfig {
+    /// Read `CORS_ALLOWED_ORIGINS`, `CORS_ALLOWED_METHODS`, `CORS_ALLOWED_HEADERS` (all comma
+    /// separated), `CORS_ALLOW_CREDENTIALS` and `CORS_MAX_AGE` (seconds).
+    ///
+    /// Returns `None` when no origins are configured, i.e. CORS is disabled. `*` turns
+    /// credentials off.
+    pub fn from_env() -> Option<Self> {
+        let list = |name: &str| {
+            std::env::var(name).ok().map(|value| {
+                value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|item| !item.is_empty())
+                    .map(String::from)
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        let default = Self::default();
+        let allowed_origins = list("CORS_ALLOWED_ORIGINS").filter(|origins| !origins.is_empty())?;
+        let allowed_methods = match list("CORS_ALLOWED_METHODS") {
+            Some(methods) => methods
+                .iter()
+                .filter_map(|method| method.to_ascii_uppercase().parse().ok())
+                .collect(),
+            None => default.allowed_methods,
+        };
+        let allow_credentials = Self::allow_credentials(
+            &allowed_origins,
+            std::env::var("CORS_ALLOW_CREDENTIALS").ok().as_deref(),
+        );
+        let max_age = match std::env::var("CORS_MAX_AGE") {
+            Ok(value) => value.parse().ok().map(Duration::from_secs),
+            Err(_) => default.max_age,
+        };
+
+        Some(Self {
+            allowed_origins,
+            allowed_methods,
+            allowed_headers: list("CORS_ALLOWED_HEADERS").unwrap_or(default.allowed_headers),
+            allow_credentials,
+            max_age,
+        })
+    }
+
+    /// `CORS_ALLOW_CREDENTIALS` for `allowed_origins`, never with `*`
+    fn allow_credentials(allowed_origins: &[String], value: Option<&str>) -> bool {
+        let allow = match value {
+            Some(value) => value.eq_ignore_ascii_case("true"),
+            None => Self::default().allow_credentials,
+        };
+        if allow && allowed_origins.iter().any(|allowed| allowed == "*") {
+            tracing::warn!("CORS_ALLOWED_ORIGINS is `*`, credentials are not allowed");
+            return false;
+        }
+        allow
+    }
+
+    pub fn is_allowed_origin(&self, origin: &str) -> bool {
+        self.allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+    }
+
+    /// Answer a preflight request, `None` if `request` is not one
+    pub fn preflight(&self, request: &Request) -> Option<Response> {
+        if request.request_context.http.method != Method::OPTIONS
+            || !request.headers.contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+        {
+            return None;
+        }
+        let origin = header(request, ORIGIN.as_str())?;
+
+        let requested_method = header(request, ACCESS_CONTROL_REQUEST_METHOD.as_str())
+            .and_then(|method| method.parse::<Method>().ok());
+        let method_allowed = requested_method
+            .map(|method| self.allowed_methods.contains(&method))
+            .unwrap_or(false);
+        if !self.is_allowed_origin(origin) || !method_allowed {
+            tracing::info!("rejected CORS preflight from `{}`", origin);
+            let mut res = response(403, None);
+            res.headers.insert(VARY, HeaderValue::from_static("Origin"));
+            return Some(res);
+        }
+
+        let mut res = response(204, None);
+        self.apply(Some(origin), &mut res);
+        let methods = self
+            .allowed_methods
+            .iter()
+            .map(Method::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+        insert(&mut res, ACCESS_CONTROL_ALLOW_METHODS.as_str(), &methods);
+        insert(
+            &mut res,
+            ACCESS_CONTROL_ALLOW_HEADERS.as_str(),
+            &self.allowed_headers.join(", "),
+        );
+        if let Some(max_age) = self.max_age {
+            insert(
+                &mut res,
+                ACCESS_CONTROL_MAX_AGE.as_str(),
+                &max_age.as_secs().to_string(),
+            );
+        }
+        Some(res)
+    }
+
+    /// Add the `Access-Control-*` headers for `origin` to an actual response
+    pub fn apply(&self, origin: Option<&str>, res: &mut Response) {
+        res.headers.append(VARY, HeaderValue::from_static("Origin"));
+        let Some(origin) = origin.filter(|origin| self.is_allowed_origin(origin)) else {
+            return;
+        };
+
+        // any origin is only ever allowed without credentials
+        let any_origin = self.allowed_origins.iter().any(|allowed| allowed == "*");
+        let allow_origin = match any_origin {
+            true => "*",
+            false => origin,
+        };
+        insert(res, ACCESS_CONTROL_ALLOW_ORIGIN.as_str(), allow_origin);
+        if self.allow_credentials && !any_origin {
+            res.headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+}
+
+fn header<'a>(request: &'a Request, name: &str) -> Option<&'a str> {
+    request
+        .headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+}
+
+fn insert(res: &mut Response, name: &'static str, value: &str) {
+    match HeaderValue::from_str(value) {
+        Ok(value) => {
+            res.headers.insert(name, value);
+        }
+        Err(err) => tracing::error!("invalid CORS header {}: {}", name, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> CorsConfig {
+        CorsConfig {
+            allowed_origins: vec![String::from("https://app.example.com")],
+            allowed_methods: vec![Method::GET, Method::POST],
+            ..CorsConfig::default()
+        }
+    }
+
+    fn preflight_request(origin: &str, method: &str) -> Request {
+        let mut request = Request::default();
+        request.request_context.http.method = Method::OPTIONS;
+        request
+            .headers
+            .insert(ORIGIN, HeaderValue::from_str(origin).unwrap());
+        request.headers.insert(
+            ACCESS_CONTROL_REQUEST_METHOD,
+            HeaderValue::from_str(method).unwrap(),
+        );
+        request
+    }
+
+    #[test]
+    fn answers_preflight() {
+        let res = config()
+            .preflight(&preflight_request("https://app.example.com", "POST"))
+            .unwrap();
+        assert_eq!(res.status_code, 204);
+        assert_eq!(
+            res.headers[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        assert_eq!(res.headers[ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(res.headers[ACCESS_CONTROL_ALLOW_METHODS], "GET, POST");
+        assert_eq!(res.headers[ACCESS_CONTROL_MAX_AGE], "600");
+
+        let res = config()
+            .preflight(&preflight_request("https://evil.example.com", "GET"))
+            .unwrap();
+        assert_eq!(res.status_code, 403);
+        assert!(!res.headers.contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        let res = config()
+            .preflight(&preflight_request("https://app.example.com", "DELETE"))
+            .unwrap();
+        assert_eq!(res.status_code, 403);
+
+        let mut request = preflight_request("https://app.example.com", "GET");
+        request.request_context.http.method = Method::GET;
+        assert!(config().preflight(&request).is_none());
+    }
+
+    #[test]
+    fn applies_headers() {
+        let mut res = response(200, None);
+        config().apply(Some("https://app.example.com"), &mut res);
+        assert_eq!(
+            res.headers[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        assert_eq!(res.headers[VARY], "Origin");
+
+        let mut res = response(200, None);
+        config().apply(Some("https://evil.example.com"), &mut res);
+        assert!(!res.headers.contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        let any = CorsConfig {
+            allowed_origins: vec![String::from("*")],
+            allow_credentials: false,
+            ..CorsConfig::default()
+        };
+        let mut res = response(200, None);
+        any.apply(Some("https://anything.example.com"), &mut res);
+        assert_eq!(res.headers[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(!res.headers.contains_key(ACCESS_CONTROL_ALLOW_CREDENTIALS));
+    }
+
+    #[test]
+    fn never_allows_credentials_for_any_origin() {
+        let origins = |origins: &[&str]| origins.iter().map(|o| o.to_string()).collect::<Vec<_>>();
+        assert!(!CorsConfig::allow_credentials(&origins(&["*"]), Some("true")));
+        assert!(!CorsConfig::allow_credentials(
+            &origins(&["https://app.example.com", "*"]),
+            Some("true")
+        ));
+        assert!(CorsConfig::allow_credentials(
+            &origins(&["https://app.example.com"]),
+            Some("TRUE")
+        ));
+        assert!(!CorsConfig::allow_credentials(
+            &origins(&["https://app.example.com"]),
+            Some("false")
+        ));
+
+        // nor when built by hand
+        let any = CorsConfig {
+            allowed_origins: vec![String::from("*")],
+            ..CorsConfig::default()
+        };
+        let mut res = response(200, None);
+        any.apply(Some("https://evil.example.com"), &mut res);
+        assert_eq!(res.headers[ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+        assert!(!res.headers.contains_key(ACCESS_CONTROL_ALLOW_CREDENTIALS));
+    }
+}
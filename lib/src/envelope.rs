@@ -0,0 +1,409 @@
+//! Envelope encryption: payloads are sealed with AES-256-GCM under a data key, and the data
+//! key is stored next to them wrapped by a key provider (KMS, or local keys for tests).
+
+use aes_gcm::{
+    aead::{Aead, OsRng, Payload},
+    AeadCore, Aes256Gcm, KeyInit,
+};
+use aws_sdk_kms::{primitives::Blob, types::DataKeySpec};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::error::Error;
+
+const NONCE_LEN: usize = 12;
+/// How long one data key seals new payloads before a fresh one is generated
+const DATA_KEY_TTL: Duration = Duration::from_secs(300);
+/// Unwrapped data keys kept in memory, so reads of recent items skip the provider
+const UNWRAPPED_CACHE_SIZE: usize = 64;
+
+/// A 256 bit AES-GCM key and the id that names it in what it seals
+#[derive(Clone)]
+pub struct AesKey {
+    id: String,
+    cipher: Aes256Gcm,
+}
+
+impl std::fmt::Debug for AesKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AesKey").field("id", &self.id).finish_non_exhaustive()
+    }
+}
+
+impl AesKey {
+    pub fn new(id: &str, key: &[u8]) -> std::result::Result<Self, Error> {
+        if id.is_empty() || !id.chars().all(|c| c.is_ascii_alphanumeric() || "-_".contains(c)) {
+            return Err(Error::config(format!("invalid key id `{id}`")));
+        }
+        let cipher = Aes256Gcm::new_from_slice(key)
+            .map_err(|_| Error::config(format!("key `{id}` is not 32 bytes")))?;
+        Ok(Self {
+            id: id.to_owned(),
+            cipher,
+        })
+    }
+
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub(crate) fn cipher(&self) -> &Aes256Gcm {
+        &self.cipher
+    }
+
+    /// Keys from `<id>:<base64 key>` pairs separated by commas, the first one seals new
+    /// payloads and the others only open those sealed before a rotation
+    pub fn parse_list(value: &str) -> std::result::Result<Vec<Self>, Error> {
+        let keys = value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (id, key) = entry
+                    .split_once(':')
+                    .ok_or(Error::config("keys must be `<id>:<base64 key>`"))?;
+                let key = STANDARD
+                    .decode(key.trim())
+                    .map_err(|_| Error::config(format!("key `{id}` is not base64")))?;
+                Self::new(id.trim(), &key)
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        if keys.is_empty() {
+            return Err(Error::config("no keys configured"));
+        }
+        Ok(keys)
+    }
+}
+
+/// Wraps and unwraps data keys
+#[derive(Debug, Clone)]
+pub enum KeyProvider {
+    /// A KMS key by id, ARN or alias, KMS keeps old key material for rotated keys
+    Kms {
+        client: aws_sdk_kms::Client,
+        key_id: String,
+    },
+    /// Local keys, the first wraps new data keys and the others only unwrap old ones
+    Local(Vec<AesKey>),
+}
+
+impl KeyProvider {
+    pub async fn kms(key_id: &str) -> Self {
+        let config = aws_config::load_from_env().await;
+        KeyProvider::Kms {
+            client: aws_sdk_kms::Client::new(&config),
+            key_id: key_id.to_owned(),
+        }
+    }
+
+    /// Keys from a file of `<id>:<base64 key>` pairs, the format of `AesKey::parse_list`
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let value = std::fs::read_to_string(path).map_err(|err| {
+            Error::config(format!("failed to read key file `{}`", path.display())).with_source(err)
+        })?;
+        Ok(KeyProvider::Local(AesKey::parse_list(
+            &value.lines().collect::<Vec<_>>().join(","),
+        )?))
+    }
+
+    /// `SESSION_ENCRYPTION=kms` wraps with `SESSION_KMS_KEY_ID`, `SESSION_ENCRYPTION=file`
+    /// with the keys in `SESSION_KEY_FILE`, unset means no encryption
+    pub async fn from_env() -> Result<Option<Self>, Error> {
+        Self::from_env_prefixed("SESSION").await
+    }
+
+    /// `from_env` with `prefix` in place of `SESSION`, e.g. `TOKEN_ENCRYPTION`
+    pub async fn from_env_prefixed(prefix: &str) -> Result<Option<Self>, Error> {
+        let var = |suffix: &str| {
+            let name = format!("{prefix}_{suffix}");
+            std::env::var(&name).map_err(|_| Error::config(format!("ENV VAR {name} not set")))
+        };
+        match std::env::var(format!("{prefix}_ENCRYPTION")).as_deref() {
+            Ok("kms") => Ok(Some(Self::kms(&var("KMS_KEY_ID")?).await)),
+            Ok("file") => Ok(Some(Self::from_file(var("KEY_FILE")?)?)),
+            Ok("" | "off") | Err(_) => Ok(None),
+            Ok(other) => Err(Error::config(format!(
+                "unknown {prefix}_ENCRYPTION `{other}`"
+            ))),
+        }
+    }
+
+    /// A new data key, returns its cipher, the wrapped key and the wrapping key's id
+    async fn generate(&self) -> Result<(Aes256Gcm, Vec<u8>, String), Error> {
+        match self {
+            KeyProvider::Kms { client, key_id } => {
+                let res = client
+                    .generate_data_key()
+                    .key_id(key_id)
+                    .key_spec(DataKeySpec::Aes256)
+                    .send()
+                    .await
+                    .map_err(|err| data_key_error("kms generate data key failed", err))?;
+                let (Some(plaintext), Some(wrapped)) = (res.plaintext(), res.ciphertext_blob())
+                else {
+                    return Err(data_key_error("kms returned no data key", "missing blob"));
+                };
+                let cipher = Aes256Gcm::new_from_slice(plaintext.as_ref())
+                    .map_err(|_| data_key_error("kms data key is not 32 bytes", "bad length"))?;
+                let wrapping_key = res.key_id().unwrap_or(key_id);
+                Ok((cipher, wrapped.as_ref().to_vec(), wrapping_key.to_owned()))
+            }
+            KeyProvider::Local(keys) => {
+                let key = keys
+                    .first()
+                    .ok_or(Error::config("no local encryption keys configured"))?;
+                let data_key = Aes256Gcm::generate_key(&mut OsRng);
+                let wrapped = seal(key.cipher(), &data_key, key.id().as_bytes())?;
+                Ok((Aes256Gcm::new(&data_key), wrapped, key.id().to_owned()))
+            }
+        }
+    }
+
+    async fn unwrap(&self, key_id: &str, wrapped: &[u8]) -> Result<Aes256Gcm, Error> {
+        let plaintext = match self {
+            KeyProvider::Kms { client, .. } => client
+                .decrypt()
+                .key_id(key_id)
+                .ciphertext_blob(Blob::new(wrapped))
+                .send()
+                .await
+                .map_err(|err| data_key_error("kms decrypt failed", err))?
+                .plaintext()
+                .map(|plaintext| plaintext.as_ref().to_vec())
+                .ok_or(data_key_error("kms returned no plaintext", "missing blob"))?,
+            KeyProvider::Local(keys) => {
+                let key = keys
+                    .iter()
+                    .find(|key| key.id() == key_id)
+                    .ok_or(data_key_error(
+                        "unknown wrapping key",
+                        format!("key id `{key_id}`"),
+                    ))?;
+                open(key.cipher(), wrapped, key_id.as_bytes())?
+            }
+        };
+        Aes256Gcm::new_from_slice(&plaintext)
+            .map_err(|_| data_key_error("data key is not 32 bytes", "bad length"))
+    }
+}
+
+/// A payload sealed under a wrapped data key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sealed {
+    /// The provider key that wrapped `data_key`
+    pub key_id: String,
+    /// The wrapped data key, base64
+    pub data_key: String,
+    /// Nonce and ciphertext, base64
+    pub ciphertext: String,
+}
+
+/// Seals payloads with envelope encryption.
+///
+/// A data key is reused for a few minutes to keep KMS calls off the request path, and
+/// unwrapped data keys are cached so that reads of recently written items are local.
+#[derive(Debug, Clone)]
+pub struct Envelope {
+    provider: Arc<KeyProvider>,
+    cache: Arc<Mutex<Cache>>,
+    data_key_ttl: Duration,
+}
+
+#[derive(Default)]
+struct Cache {
+    current: Option<(Aes256Gcm, Sealed, Instant)>,
+    unwrapped: HashMap<(String, String), Aes256Gcm>,
+}
+
+impl std::fmt::Debug for Cache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Cache")
+            .field("unwrapped", &self.unwrapped.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Envelope {
+    pub fn new(provider: KeyProvider) -> Self {
+        Self {
+            provider: Arc::new(provider),
+            cache: Arc::new(Mutex::new(Cache::default())),
+            data_key_ttl: DATA_KEY_TTL,
+        }
+    }
+
+    /// How long a data key seals new payloads, zero generates one per payload
+    pub fn with_data_key_ttl(mut self, data_key_ttl: Duration) -> Self {
+        self.data_key_ttl = data_key_ttl;
+        self
+    }
+
+    /// Seal `plaintext`, `aad` must be given again to open it, e.g. the item's key
+    pub async fn seal(&self, plaintext: &[u8], aad: &[u8]) -> Result<Sealed, Error> {
+        let (cipher, mut sealed) = self.data_key().await?;
+        sealed.ciphertext = STANDARD.encode(seal(&cipher, plaintext, aad)?);
+        Ok(sealed)
+    }
+
+    pub async fn open(&self, sealed: &Sealed, aad: &[u8]) -> Result<Vec<u8>, Error> {
+        let cache_key = (sealed.key_id.clone(), sealed.data_key.clone());
+        let cached = self.cache().unwrapped.get(&cache_key).cloned();
+        let cipher = match cached {
+            Some(cipher) => cipher,
+            None => {
+                let wrapped = STANDARD
+                    .decode(&sealed.data_key)
+                    .map_err(|err| data_key_error("wrapped data key is not base64", err))?;
+                let cipher = self.provider.unwrap(&sealed.key_id, &wrapped).await?;
+                let mut cache = self.cache();
+                if cache.unwrapped.len() >= UNWRAPPED_CACHE_SIZE {
+                    cache.unwrapped.clear();
+                }
+                cache.unwrapped.insert(cache_key, cipher.clone());
+                cipher
+            }
+        };
+        let ciphertext = STANDARD
+            .decode(&sealed.ciphertext)
+            .map_err(|err| data_key_error("ciphertext is not base64", err))?;
+        open(&cipher, &ciphertext, aad)
+    }
+
+    /// The current data key, or a new one once it is older than the data key TTL
+    async fn data_key(&self) -> Result<(Aes256Gcm, Sealed), Error> {
+        if let Some((cipher, sealed, created)) = &self.cache().current {
+            if created.elapsed() < self.data_key_ttl {
+                return Ok((cipher.clone(), sealed.clone()));
+            }
+        }
+
+        let (cipher, wrapped, key_id) = self.provider.generate().await?;
+        tracing::info!("generated a data key wrapped by `{}`", key_id);
+        let sealed = Sealed {
+            key_id,
+            data_key: STANDARD.encode(wrapped),
+            ciphertext: String::new(),
+        };
+        let mut cache = self.cache();
+        cache.unwrapped.insert(
+            (sealed.key_id.clone(), sealed.data_key.clone()),
+            cipher.clone(),
+        );
+        cache.current = Some((cipher.clone(), sealed.clone(), Instant::now()));
+        Ok((cipher, sealed))
+    }
+
+    fn cache(&self) -> std::sync::MutexGuard<'_, Cache> {
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Nonce followed by the ciphertext
+fn seal(cipher: &Aes256Gcm, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, Error> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(
+            &nonce,
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| Error::internal("failed to encrypt payload"))?;
+    let mut sealed = nonce.to_vec();
+    sealed.extend(ciphertext);
+    Ok(sealed)
+}
+
+fn open(cipher: &Aes256Gcm, sealed: &[u8], aad: &[u8]) -> Result<Vec<u8>, Error> {
+    if sealed.len() < NONCE_LEN {
+        return Err(Error::internal("sealed payload is too short"));
+    }
+    let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+    cipher
+        .decrypt(
+            nonce.into(),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| Error::internal("failed to decrypt payload"))
+}
+
+fn data_key_error(message: &str, source: impl Into<crate::error::BoxError>) -> Error {
+    Error::Secret {
+        name: String::from("data key"),
+        source: Some(format!("{message}: {}", source.into()).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(keys: &[(&str, u8)]) -> KeyProvider {
+        KeyProvider::Local(
+            keys.iter()
+                .map(|(id, byte)| AesKey::new(id, &[*byte; 32]).unwrap())
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn seals_and_opens() {
+        let envelope = Envelope::new(local(&[("k1", 1)]));
+        let sealed = envelope.seal(b"secret", b"id-1").await.unwrap();
+        assert_eq!(sealed.key_id, "k1");
+        assert_eq!(envelope.open(&sealed, b"id-1").await.unwrap(), b"secret");
+        assert!(envelope.open(&sealed, b"id-2").await.is_err());
+
+        let again = envelope.seal(b"secret", b"id-1").await.unwrap();
+        assert_eq!(again.data_key, sealed.data_key);
+        assert_ne!(again.ciphertext, sealed.ciphertext);
+
+        let fresh = Envelope::new(local(&[("k1", 1)])).with_data_key_ttl(Duration::ZERO);
+        let first = fresh.seal(b"secret", b"id-1").await.unwrap();
+        let second = fresh.seal(b"secret", b"id-1").await.unwrap();
+        assert_ne!(first.data_key, second.data_key);
+    }
+
+    #[tokio::test]
+    async fn opens_items_sealed_before_a_rotation() {
+        let sealed = Envelope::new(local(&[("k1", 1)]))
+            .seal(b"secret", b"id-1")
+            .await
+            .unwrap();
+
+        let rotated = Envelope::new(local(&[("k2", 2), ("k1", 1)]));
+        assert_eq!(rotated.open(&sealed, b"id-1").await.unwrap(), b"secret");
+        assert_eq!(rotated.seal(b"x", b"id-1").await.unwrap().key_id, "k2");
+
+        let retired = Envelope::new(local(&[("k2", 2)]));
+        assert_eq!(
+            retired.open(&sealed, b"id-1").await.unwrap_err().code(),
+            "secret"
+        );
+    }
+
+    #[test]
+    fn parses_key_lists() {
+        let value = format!("new:{}, old:{}", STANDARD.encode([2; 32]), STANDARD.encode([1; 32]));
+        let keys = AesKey::parse_list(&value).unwrap();
+        assert_eq!(keys[0].id, "new");
+        assert_eq!(keys[1].id, "old");
+
+        assert!(AesKey::parse_list("").is_err());
+        assert!(AesKey::parse_list(&format!("k1:{}", STANDARD.encode([1; 16]))).is_err());
+        assert!(AesKey::parse_list(&format!("k.1:{}", STANDARD.encode([1; 32]))).is_err());
+    }
+}
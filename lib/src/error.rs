@@ -1,29 +1,280 @@
-use serde::Serialize;
+use aws_sdk_dynamodb::{
+    error::SdkError,
+    operation::{delete_item::DeleteItemError, put_item::PutItemError, query::QueryError},
+};
+use aws_lambda_events::http::Method;
+use aws_sdk_ssm::operation::get_parameters::GetParametersError;
 
-#[derive(Debug, Serialize)]
-pub struct CustomError(pub String);
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
 
-impl CustomError {
-    pub fn new(e: &str) -> Box<Self> {
-        Box::new(CustomError::from(e))
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The step of a provider login that failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProviderErrorKind {
+    /// exchanging the authorization code for an access token
+    TokenExchange,
+    /// calling the provider's API with the access token
+    Api,
+    /// the user did not grant the scopes we need
+    Scope,
+    /// the provider account cannot be turned into one of our users
+    Identity,
+}
+
+impl ProviderErrorKind {
+    fn code(&self) -> &'static str {
+        match self {
+            ProviderErrorKind::TokenExchange => "provider.token_exchange",
+            ProviderErrorKind::Api => "provider.api",
+            ProviderErrorKind::Scope => "provider.scope",
+            ProviderErrorKind::Identity => "provider.identity",
+        }
     }
+}
 
+#[derive(Debug)]
+pub enum Error {
+    /// Missing or invalid configuration, e.g. an unset env var
+    Config(String),
+    /// A secret could not be loaded from the parameter store
+    Secret {
+        name: String,
+        source: Option<BoxError>,
+    },
+    /// The OAuth provider failed or refused the login
+    Provider {
+        kind: ProviderErrorKind,
+        message: String,
+        source: Option<BoxError>,
+    },
+    /// A session could not be created, loaded or destroyed
+    Session {
+        message: String,
+        source: Option<BoxError>,
+    },
+    /// The backing store failed
+    Storage {
+        message: String,
+        source: Option<BoxError>,
+    },
+    /// A bug on our side, e.g. a response that cannot be built
+    Internal {
+        message: String,
+        source: Option<BoxError>,
+    },
+    /// The request is malformed, the message is shown to the user
+    BadRequest(String),
+    /// The request has no valid session
+    Unauthorized(String),
+    /// The user is known but not allowed, the message is shown to the user
+    Forbidden(String),
+    NotFound,
+    /// The path exists but not for this method, carries the allowed methods
+    MethodNotAllowed(Vec<Method>),
 }
 
-impl From<&str> for CustomError {
-    fn from(value: &str) -> Self {
-        CustomError(value.to_owned())
+impl Error {
+    pub fn config(message: impl Into<String>) -> Self {
+        Error::Config(message.into())
+    }
+
+    pub fn provider(kind: ProviderErrorKind, message: impl Into<String>) -> Self {
+        Error::Provider {
+            kind,
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    pub fn session(message: impl Into<String>) -> Self {
+        Error::Session {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    pub fn storage(message: impl Into<String>) -> Self {
+        Error::Storage {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Error::Internal {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    /// Attach an underlying cause, kept for logging through `source()`
+    pub fn with_source(mut self, err: impl Into<BoxError>) -> Self {
+        match &mut self {
+            Error::Secret { source, .. }
+            | Error::Provider { source, .. }
+            | Error::Session { source, .. }
+            | Error::Storage { source, .. }
+            | Error::Internal { source, .. } => *source = Some(err.into()),
+            _ => {}
+        }
+        self
+    }
+
+    /// Stable machine readable code, safe to expose to clients
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Config(_) => "config",
+            Error::Secret { .. } => "secret",
+            Error::Provider { kind, .. } => kind.code(),
+            Error::Session { .. } => "session",
+            Error::Storage { .. } => "storage",
+            Error::Internal { .. } => "internal",
+            Error::BadRequest(_) => "bad_request",
+            Error::Unauthorized(_) => "unauthorized",
+            Error::Forbidden(_) => "forbidden",
+            Error::NotFound => "not_found",
+            Error::MethodNotAllowed(_) => "method_not_allowed",
+        }
+    }
+
+    pub fn status(&self) -> u16 {
+        match self {
+            Error::Config(_) | Error::Secret { .. } => 500,
+            Error::Session { .. } | Error::Storage { .. } | Error::Internal { .. } => 500,
+            Error::Provider { kind, .. } => match kind {
+                ProviderErrorKind::TokenExchange | ProviderErrorKind::Api => 502,
+                ProviderErrorKind::Scope | ProviderErrorKind::Identity => 403,
+            },
+            Error::BadRequest(_) => 400,
+            Error::Unauthorized(_) => 401,
+            Error::Forbidden(_) => 403,
+            Error::NotFound => 404,
+            Error::MethodNotAllowed(_) => 405,
+        }
+    }
+
+    /// The message shown to the user, internal details are only logged
+    pub fn public_message(&self) -> &str {
+        match self {
+            Error::Config(_)
+            | Error::Secret { .. }
+            | Error::Session { .. }
+            | Error::Storage { .. }
+            | Error::Internal { .. } => "Something went wrong on our side. Please try again later.",
+            Error::Provider { kind, message, .. } => match kind {
+                ProviderErrorKind::TokenExchange | ProviderErrorKind::Api => {
+                    "We could not complete the sign in with your provider. Please try again."
+                }
+                ProviderErrorKind::Scope | ProviderErrorKind::Identity => message,
+            },
+            Error::BadRequest(message) | Error::Unauthorized(message) | Error::Forbidden(message) => {
+                message
+            }
+            Error::NotFound => "The requested resource was not found.",
+            Error::MethodNotAllowed(_) => "The request method is not supported for this resource.",
+        }
+    }
+
+    /// The canonical reason phrase for the status, e.g. `Not Found`
+    pub fn title(&self) -> &'static str {
+        aws_lambda_events::http::StatusCode::from_u16(self.status())
+            .ok()
+            .and_then(|status| status.canonical_reason())
+            .unwrap_or("Error")
     }
 }
 
-impl std::error::Error for CustomError {
-    // this implementation required `Debug` and `Display` traits
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Secret { source, .. }
+            | Error::Provider { source, .. }
+            | Error::Session { source, .. }
+            | Error::Storage { source, .. }
+            | Error::Internal { source, .. } => source
+                .as_ref()
+                .map(|err| err.as_ref() as &(dyn std::error::Error + 'static)),
+            _ => None,
+        }
+    }
 }
 
-impl std::fmt::Display for CustomError {
-    /// Display the error struct as a JSON string
+impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        match self {
+            Error::Config(message) => write!(f, "config error: {message}"),
+            Error::Secret { name, .. } => write!(f, "failed to load secret `{name}`"),
+            Error::Provider { kind, message, .. } => write!(f, "{}: {message}", kind.code()),
+            Error::Session { message, .. } => write!(f, "session error: {message}"),
+            Error::Storage { message, .. } => write!(f, "storage error: {message}"),
+            Error::Internal { message, .. } => write!(f, "internal error: {message}"),
+            Error::BadRequest(message) => write!(f, "bad request: {message}"),
+            Error::Unauthorized(message) => write!(f, "unauthorized: {message}"),
+            Error::Forbidden(message) => write!(f, "forbidden: {message}"),
+            Error::NotFound => write!(f, "not found"),
+            Error::MethodNotAllowed(allowed) => write!(f, "method not allowed, allowed: {allowed:?}"),
+        }
     }
 }
 
+macro_rules! storage_sdk_error {
+    ($($op:ty),*) => {
+        $(impl From<SdkError<$op>> for Error {
+            fn from(err: SdkError<$op>) -> Self {
+                Error::storage("dynamodb request failed").with_source(err)
+            }
+        })*
+    };
+}
+
+storage_sdk_error!(PutItemError, DeleteItemError, QueryError);
+
+impl From<SdkError<GetParametersError>> for Error {
+    fn from(err: SdkError<GetParametersError>) -> Self {
+        Error::Secret {
+            name: String::from("ssm parameters"),
+            source: Some(err.into()),
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::provider(ProviderErrorKind::Api, "provider request failed").with_source(err)
+    }
+}
+
+impl From<serde_dynamo::Error> for Error {
+    fn from(err: serde_dynamo::Error) -> Self {
+        Error::storage("failed to (de)serialize item").with_source(err)
+    }
+}
+
+impl From<async_session::Error> for Error {
+    fn from(err: async_session::Error) -> Self {
+        Error::session("session store failed").with_source(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error as _;
+
+    #[test]
+    fn codes_and_statuses() {
+        let err = Error::provider(ProviderErrorKind::Scope, "email scope missing");
+        assert_eq!(err.code(), "provider.scope");
+        assert_eq!(err.status(), 403);
+        assert_eq!(err.public_message(), "email scope missing");
+
+        let err = Error::storage("put failed").with_source(Error::config("inner"));
+        assert_eq!(err.code(), "storage");
+        assert_eq!(err.status(), 500);
+        assert!(!err.public_message().contains("put failed"));
+        assert_eq!(err.source().unwrap().to_string(), "config error: inner");
+
+        assert_eq!(Error::NotFound.title(), "Not Found");
+    }
+}
@@ -1,12 +1,30 @@
 use aws_lambda_events::{
-    apigw::ApiGatewayV2httpResponse as Response, encodings::Body, http::HeaderMap,
+    apigw::ApiGatewayV2httpResponse as Response,
+    encodings::Body,
+    http::{
+        header::{
+            CONTENT_SECURITY_POLICY, CONTENT_TYPE, LOCATION, REFERRER_POLICY,
+            STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+        },
+        HeaderMap, HeaderName, HeaderValue,
+    },
+};
+use serde::Serialize;
+use serde_json::json;
+use std::{fmt, sync::OnceLock, time::Duration};
+
+use crate::{
+    error::Error,
+    templates::{render, Branding, ErrorPage},
 };
 
 pub fn response(status_code: i64, body: Option<Body>) -> Response {
+    let mut headers = HeaderMap::new();
+    SecurityHeaders::global().apply_defaults(&mut headers);
     Response {
         status_code,
         body,
-        headers: HeaderMap::new(),
+        headers,
         multi_value_headers: HeaderMap::new(),
         is_base64_encoded: None,
         cookies: vec![],
@@ -16,6 +34,7 @@ pub fn response(status_code: i64, body: Option<Body>) -> Response {
 pub fn html_response(status_code: i64, body: Option<Body>) -> Response {
 let mut headers = HeaderMap::new();
     headers.insert("Content-Type", "text/html".parse().unwrap());
+    SecurityHeaders::global().apply_defaults(&mut headers);
     Response {
         status_code,
         body,
@@ -25,3 +44,592 @@ let mut headers = HeaderMap::new();
         cookies: vec![],
     }
 }
+
+/// Fluent builder for API Gateway v2 responses.
+///
+/// Invalid header values or bodies are collected and returned from `build`
+/// rather than panicking half way through a handler.
+#[derive(Debug)]
+pub struct ResponseBuilder {
+    status_code: i64,
+    headers: HeaderMap,
+    cookies: Vec<String>,
+    body: Option<Body>,
+    is_base64_encoded: Option<bool>,
+    security_headers: Option<SecurityHeaders>,
+    error: Option<Error>,
+}
+
+impl ResponseBuilder {
+    pub fn new(status_code: u16) -> Self {
+        Self {
+            status_code: i64::from(status_code),
+            headers: HeaderMap::new(),
+            cookies: vec![],
+            body: None,
+            is_base64_encoded: None,
+            security_headers: Some(SecurityHeaders::global().clone()),
+            error: None,
+        }
+    }
+
+    pub fn ok() -> Self {
+        Self::new(200)
+    }
+
+    pub fn status(mut self, status_code: u16) -> Self {
+        self.status_code = i64::from(status_code);
+        self
+    }
+
+    /// Insert a header, replacing any previous value
+    pub fn header<K, V>(mut self, name: K, value: V) -> Self
+    where
+        K: TryInto<HeaderName>,
+        K::Error: std::error::Error + Send + Sync + 'static,
+        V: TryInto<HeaderValue>,
+        V::Error: std::error::Error + Send + Sync + 'static,
+    {
+        if self.error.is_some() {
+            return self;
+        }
+        let name = match name.try_into() {
+            Ok(name) => name,
+            Err(err) => return self.fail(Error::internal("invalid header name").with_source(err)),
+        };
+        match value.try_into() {
+            Ok(value) => {
+                self.headers.insert(name, value);
+                self
+            }
+            Err(err) => self.fail(
+                Error::internal(format!("invalid value for header `{name}`")).with_source(err),
+            ),
+        }
+    }
+
+    /// Temporary redirect to `url`, keeping a 3xx status if one was already set
+    pub fn redirect(mut self, url: &str) -> Self {
+        if !(300..400).contains(&self.status_code) {
+            self.status_code = 307;
+        }
+        self.header(LOCATION, url)
+    }
+
+    pub fn json<T: Serialize + ?Sized>(mut self, value: &T) -> Self {
+        match serde_json::to_string(value) {
+            Ok(body) => {
+                self.body = Some(Body::Text(body));
+                self.header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
+            }
+            Err(err) => self.fail(Error::internal("failed to serialize body").with_source(err)),
+        }
+    }
+
+    pub fn html(mut self, body: impl Into<String>) -> Self {
+        self.body = Some(Body::Text(body.into()));
+        self.header(CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"))
+    }
+
+    pub fn text(mut self, body: impl Into<String>) -> Self {
+        self.body = Some(Body::Text(body.into()));
+        self.header(CONTENT_TYPE, HeaderValue::from_static("text/plain; charset=utf-8"))
+    }
+
+    /// Binary body, sent base64 encoded as API Gateway requires
+    pub fn binary(mut self, body: Vec<u8>, content_type: &str) -> Self {
+        self.body = Some(Body::Binary(body));
+        self.is_base64_encoded = Some(true);
+        self.header(CONTENT_TYPE, content_type)
+    }
+
+    /// Add a cookie through the v2 `cookies` field, API Gateway turns each into a `Set-Cookie`
+    pub fn set_cookie(mut self, cookie: &Cookie) -> Self {
+        match cookie.validate() {
+            Ok(()) => {
+                self.cookies.push(cookie.to_string());
+                self
+            }
+            Err(err) => self.fail(err),
+        }
+    }
+
+    /// Replace the default security headers for this response, `None` sends none
+    pub fn security_headers(mut self, policy: Option<SecurityHeaders>) -> Self {
+        self.security_headers = policy;
+        self
+    }
+
+    pub fn build(mut self) -> Result<Response, Error> {
+        if let Some(err) = self.error {
+            return Err(err);
+        }
+        if let Some(policy) = &self.security_headers {
+            policy.apply_defaults(&mut self.headers);
+        }
+        Ok(Response {
+            status_code: self.status_code,
+            body: self.body,
+            headers: self.headers,
+            multi_value_headers: HeaderMap::new(),
+            is_base64_encoded: self.is_base64_encoded,
+            cookies: self.cookies,
+        })
+    }
+
+    fn fail(mut self, err: Error) -> Self {
+        self.error.get_or_insert(err);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+/// A `Set-Cookie` value, defaults to `Path=/; HttpOnly; Secure; SameSite=Lax`
+#[derive(Debug, Clone)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    max_age: Option<Duration>,
+    http_only: bool,
+    secure: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            value: value.to_owned(),
+            path: Some(String::from("/")),
+            max_age: None,
+            http_only: true,
+            secure: true,
+            same_site: Some(SameSite::Lax),
+        }
+    }
+
+    /// An expired, empty cookie that makes the browser drop `name`
+    pub fn removal(name: &str) -> Self {
+        Self::new(name, "").max_age(Duration::ZERO)
+    }
+
+    pub fn path(mut self, path: Option<&str>) -> Self {
+        self.path = path.map(String::from);
+        self
+    }
+
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: Option<SameSite>) -> Self {
+        self.same_site = same_site;
+        self
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let is_token = |c: char| c.is_ascii_graphic() && !"()<>@,;:\\\"/[]?={}".contains(c);
+        let is_cookie_octet = |c: char| c.is_ascii_graphic() && !"\",;\\".contains(c);
+        let is_path = |c: char| (c.is_ascii_graphic() || c == ' ') && c != ';';
+
+        if self.name.is_empty() || !self.name.chars().all(is_token) {
+            return Err(Error::internal(format!("invalid cookie name `{}`", self.name)));
+        }
+        if !self.value.chars().all(is_cookie_octet) {
+            return Err(Error::internal(format!("invalid value for cookie `{}`", self.name)));
+        }
+        if !self.path.iter().all(|path| path.chars().all(is_path)) {
+            return Err(Error::internal(format!("invalid path for cookie `{}`", self.name)));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={path}")?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        match self.same_site {
+            Some(SameSite::Strict) => write!(f, "; SameSite=Strict"),
+            Some(SameSite::Lax) => write!(f, "; SameSite=Lax"),
+            Some(SameSite::None) => write!(f, "; SameSite=None"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Security headers added to every response unless a handler sets its own.
+///
+/// `None` leaves a header out. The default CSP allows the inline styles and https logo
+/// of our templates and nothing else.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityHeaders {
+    pub content_security_policy: Option<String>,
+    pub strict_transport_security: Option<String>,
+    pub content_type_options: Option<String>,
+    pub referrer_policy: Option<String>,
+    pub frame_options: Option<String>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self {
+            content_security_policy: Some(String::from(
+                "default-src 'none'; img-src 'self' https:; style-src 'unsafe-inline'; \
+                 form-action 'self'; base-uri 'none'; frame-ancestors 'none'",
+            )),
+            strict_transport_security: Some(String::from("max-age=63072000; includeSubDomains")),
+            content_type_options: Some(String::from("nosniff")),
+            referrer_policy: Some(String::from("no-referrer")),
+            frame_options: Some(String::from("DENY")),
+        }
+    }
+}
+
+impl SecurityHeaders {
+    /// No security headers at all
+    pub fn none() -> Self {
+        Self {
+            content_security_policy: None,
+            strict_transport_security: None,
+            content_type_options: None,
+            referrer_policy: None,
+            frame_options: None,
+        }
+    }
+
+    /// The defaults, with `SECURITY_CSP`, `SECURITY_HSTS`, `SECURITY_REFERRER_POLICY` and
+    /// `SECURITY_FRAME_OPTIONS` replacing a header; `off` drops it.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let var = |name: &str, default: Option<String>| match std::env::var(name) {
+            Ok(value) if value.eq_ignore_ascii_case("off") => None,
+            Ok(value) if HeaderValue::from_str(&value).is_ok() => Some(value),
+            Ok(value) => {
+                tracing::warn!("ignoring invalid header value {name}=`{value}`");
+                default
+            }
+            Err(_) => default,
+        };
+        Self {
+            content_security_policy: var("SECURITY_CSP", default.content_security_policy),
+            strict_transport_security: var("SECURITY_HSTS", default.strict_transport_security),
+            content_type_options: default.content_type_options,
+            referrer_policy: var("SECURITY_REFERRER_POLICY", default.referrer_policy),
+            frame_options: var("SECURITY_FRAME_OPTIONS", default.frame_options),
+        }
+    }
+
+    /// The policy from the environment, read once per cold start
+    pub fn global() -> &'static SecurityHeaders {
+        static SECURITY_HEADERS: OnceLock<SecurityHeaders> = OnceLock::new();
+        SECURITY_HEADERS.get_or_init(SecurityHeaders::from_env)
+    }
+
+    fn headers(&self) -> [(HeaderName, Option<&String>); 5] {
+        [
+            (CONTENT_SECURITY_POLICY, self.content_security_policy.as_ref()),
+            (STRICT_TRANSPORT_SECURITY, self.strict_transport_security.as_ref()),
+            (X_CONTENT_TYPE_OPTIONS, self.content_type_options.as_ref()),
+            (REFERRER_POLICY, self.referrer_policy.as_ref()),
+            (X_FRAME_OPTIONS, self.frame_options.as_ref()),
+        ]
+    }
+
+    /// Add the policy's headers that are not already present
+    pub fn apply_defaults(&self, headers: &mut HeaderMap) {
+        for (name, value) in self.headers() {
+            let Some(value) = value.and_then(|value| HeaderValue::from_str(value).ok()) else {
+                continue;
+            };
+            headers.entry(name).or_insert(value);
+        }
+    }
+
+    /// Make the headers match the policy exactly, replacing or removing what is there
+    pub fn enforce(&self, headers: &mut HeaderMap) {
+        for (name, value) in self.headers() {
+            match value.and_then(|value| HeaderValue::from_str(value).ok()) {
+                Some(value) => {
+                    headers.insert(name, value);
+                }
+                None => {
+                    headers.remove(name);
+                }
+            }
+        }
+    }
+}
+
+/// Render an error as `application/problem+json` (RFC 7807) or as an HTML page,
+/// depending on the request's `Accept` header. The error detail is logged, never sent.
+pub fn error_response(err: &Error, accept: Option<&str>) -> Response {
+    if err.status() >= 500 {
+        tracing::error!("{} {}: {:?}", err.status(), err, err);
+    } else {
+        tracing::info!("{} {}", err.status(), err);
+    }
+
+    let status_code = i64::from(err.status());
+    if prefers_json(accept) {
+        let problem = json!({
+            "type": "about:blank",
+            "title": err.title(),
+            "status": err.status(),
+            "detail": err.public_message(),
+            "code": err.code(),
+        });
+        let mut res = response(status_code, Some(Body::Text(problem.to_string())));
+        res.headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        return res;
+    }
+
+    let page = render(&ErrorPage {
+        branding: Branding::global(),
+        status: err.status(),
+        title: err.title(),
+        message: err.public_message(),
+    })
+    .unwrap_or_else(|render_err| {
+        tracing::error!("failed to render error page {:?}", render_err);
+        format!(
+            "<h1>{} {}</h1><p>{}</p>",
+            err.status(),
+            escape_html(err.title()),
+            escape_html(err.public_message())
+        )
+    });
+    html_response(status_code, Some(Body::Text(page)))
+}
+
+/// Whether the client asked for JSON rather than HTML.
+///
+/// Compares the highest quality given to a JSON media type against the one given to
+/// `text/html`; browsers send `text/html` first so they keep getting pages.
+pub fn prefers_json(accept: Option<&str>) -> bool {
+    let Some(accept) = accept else {
+        return false;
+    };
+
+    let mut json_q = 0.0_f32;
+    let mut html_q = 0.0_f32;
+    for range in accept.split(',') {
+        let mut parts = range.split(';').map(str::trim);
+        let media_type = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let q = parts
+            .find_map(|param| param.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        match media_type.as_str() {
+            "application/json" | "application/problem+json" => json_q = json_q.max(q),
+            "text/html" => html_q = html_q.max(q),
+            _ => {}
+        }
+    }
+    json_q > 0.0 && json_q > html_q
+}
+
+/// The value of cookie `name` from the request's `cookies`
+pub fn find_cookie<'a>(cookies: &'a [String], name: &str) -> Option<&'a str> {
+    cookies
+        .iter()
+        .flat_map(|header| header.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+/// Compare secrets without stopping at the first difference, so the time taken does not
+/// reveal how much of a guess was right
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+pub fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#x27;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_json() {
+        assert!(!prefers_json(None));
+        assert!(!prefers_json(Some("*/*")));
+        assert!(prefers_json(Some("application/json")));
+        assert!(prefers_json(Some("application/problem+json, text/html;q=0.5")));
+        assert!(!prefers_json(Some(
+            "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"
+        )));
+        assert!(!prefers_json(Some("application/json;q=0")));
+    }
+
+    #[test]
+    fn compares_in_constant_time() {
+        assert!(constant_time_eq(b"state", b"state"));
+        assert!(!constant_time_eq(b"state", b"stale"));
+        assert!(!constant_time_eq(b"state", b"stat"));
+    }
+
+    #[test]
+    fn finds_cookies() {
+        let cookies = vec![
+            String::from("theme=dark"),
+            String::from("SESSION=abc=; other=1"),
+        ];
+        assert_eq!(find_cookie(&cookies, "SESSION"), Some("abc="));
+        assert_eq!(find_cookie(&cookies, "other"), Some("1"));
+        assert_eq!(find_cookie(&cookies, "SESS"), None);
+    }
+
+    #[test]
+    fn builds_responses() {
+        let res = ResponseBuilder::ok()
+            .redirect("https://example.com/protected")
+            .set_cookie(&Cookie::new("SESSION", "abc").max_age(Duration::from_secs(60)))
+            .build()
+            .unwrap();
+        assert_eq!(res.status_code, 307);
+        assert_eq!(res.headers["Location"], "https://example.com/protected");
+        assert_eq!(
+            res.cookies,
+            vec!["SESSION=abc; Path=/; Max-Age=60; HttpOnly; Secure; SameSite=Lax"]
+        );
+
+        let res = ResponseBuilder::new(201)
+            .json(&json!({ "ok": true }))
+            .build()
+            .unwrap();
+        assert_eq!(res.status_code, 201);
+        assert_eq!(res.headers["Content-Type"], "application/json");
+
+        let res = ResponseBuilder::ok()
+            .binary(vec![0, 1, 2], "image/png")
+            .build()
+            .unwrap();
+        assert_eq!(res.is_base64_encoded, Some(true));
+    }
+
+    #[test]
+    fn applies_security_headers() {
+        let res = ResponseBuilder::ok().html("<p>hi</p>").build().unwrap();
+        assert_eq!(res.headers["Referrer-Policy"], "no-referrer");
+        assert_eq!(res.headers["X-Content-Type-Options"], "nosniff");
+        assert_eq!(res.headers["X-Frame-Options"], "DENY");
+        assert!(res.headers["Content-Security-Policy"]
+            .to_str()
+            .unwrap()
+            .contains("frame-ancestors 'none'"));
+        assert!(res.headers.contains_key("Strict-Transport-Security"));
+
+        let res = ResponseBuilder::ok()
+            .header("Content-Security-Policy", "default-src 'self'")
+            .build()
+            .unwrap();
+        assert_eq!(res.headers["Content-Security-Policy"], "default-src 'self'");
+
+        let res = ResponseBuilder::ok()
+            .security_headers(Some(SecurityHeaders {
+                frame_options: None,
+                ..SecurityHeaders::default()
+            }))
+            .build()
+            .unwrap();
+        assert!(!res.headers.contains_key("X-Frame-Options"));
+
+        let mut res = html_response(200, None);
+        assert!(res.headers.contains_key("X-Frame-Options"));
+        SecurityHeaders::none().enforce(&mut res.headers);
+        assert!(!res.headers.contains_key("X-Frame-Options"));
+        assert_eq!(res.headers["Content-Type"], "text/html");
+    }
+
+    #[test]
+    fn builder_reports_invalid_values() {
+        let res = ResponseBuilder::ok()
+            .redirect("https://example.com/\ninjected")
+            .build();
+        assert_eq!(res.unwrap_err().code(), "internal");
+
+        let res = ResponseBuilder::ok()
+            .set_cookie(&Cookie::new("SESSION", "a;b"))
+            .build();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn error_response_formats() {
+        let err = Error::BadRequest(String::from("missing <code>"));
+
+        let res = error_response(&err, Some("application/json"));
+        assert_eq!(res.status_code, 400);
+        assert_eq!(res.headers["Content-Type"], "application/problem+json");
+        let Some(Body::Text(body)) = res.body else {
+            panic!("expected a text body")
+        };
+        let problem: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(problem["title"], "Bad Request");
+        assert_eq!(problem["status"], 400);
+        assert_eq!(problem["detail"], "missing <code>");
+        assert_eq!(problem["code"], "bad_request");
+
+        let res = error_response(&err, Some("text/html"));
+        assert_eq!(res.headers["Content-Type"], "text/html");
+        let Some(Body::Text(body)) = res.body else {
+            panic!("expected a text body")
+        };
+        assert!(body.contains("missing &lt;code&gt;"));
+
+        let err = Error::storage("table `sessions` is gone");
+        let res = error_response(&err, Some("application/json"));
+        assert_eq!(res.status_code, 500);
+        let Some(Body::Text(body)) = res.body else {
+            panic!("expected a text body")
+        };
+        assert!(!body.contains("sessions"));
+    }
+}
@@ -1,9 +1,18 @@
 pub mod aws;
+pub mod base_url;
+pub mod cors;
+pub mod envelope;
 pub mod error;
 pub mod oauth;
 pub mod session;
 pub mod model;
 pub mod helpers;
+pub mod router;
+pub mod secrets;
+pub mod signin;
+pub mod templates;
+pub mod tokens;
+pub mod users;
 
 // TODO add google mod
 // const GOOGLE_AUTH_URL: &str = "https://accounts.google.com/o/oauth2/v2/auth";
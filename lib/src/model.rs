@@ -1,8 +1,80 @@
 use serde::{Deserialize, Serialize};
 
+use crate::oauth::Scopes;
+
 pub static COOKIE_NAME: &str = "SESSION";
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Default, Serialize, Deserialize)]
 pub struct User {
+    /// The persisted user's id, see `crate::users`, empty in sessions stored before users
+    /// were persisted
+    #[serde(default)]
+    pub id: String,
     pub email: String,
+    /// GitHub org logins, lowercase, when a membership policy is configured
+    #[serde(default)]
+    pub orgs: Vec<String>,
+    /// `<org>/<team>` slugs, lowercase, when the policy names teams
+    #[serde(default)]
+    pub teams: Vec<String>,
+    /// What the provider granted, as it reported it
+    #[serde(default)]
+    pub scopes: Scopes,
+    /// The provider identity the session signed in with, e.g. `github:1234`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    /// The identity whose provider grant the session lasts as long as, see
+    /// `crate::oauth::github::access_token`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grant: Option<String>,
+}
+
+/// The `lambda` context `auth_fn` hands to API Gateway, and the identity
+/// document protected endpoints return to JSON clients
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthContext {
+    pub user_id: String,
+    pub email: String,
+    /// Comma separated, API Gateway only passes flat values on
+    #[serde(default)]
+    pub orgs: String,
+    /// Comma separated `<org>/<team>` slugs
+    #[serde(default)]
+    pub teams: String,
+    /// Comma separated granted scopes
+    #[serde(default)]
+    pub scopes: String,
+}
+
+impl AuthContext {
+    /// Read the context back from the authorizer map of a request
+    pub fn from_lambda(
+        lambda: &std::collections::HashMap<String, serde_json::Value>,
+    ) -> Option<Self> {
+        let map = lambda
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect::<serde_json::Map<_, _>>();
+        serde_json::from_value(serde_json::Value::Object(map)).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::session_user;
+    use async_session::Session;
+
+    #[test]
+    fn reads_sessions_stored_before_users_had_ids() {
+        let user = r#"{\"email\":\"octo@example.com\"}"#;
+        let json = format!(r#"{{"id": "abc", "expiry": null, "data": {{"user": "{user}"}}}}"#);
+        let session: Session = serde_json::from_str(&json).unwrap();
+
+        let user = session.get::<User>("user").unwrap();
+        assert_eq!(user.email, "octo@example.com");
+        assert_eq!(user.id, "");
+        // not signed in as anyone the user store knows
+        assert_eq!(session_user(&session), None);
+    }
 }
@@ -0,0 +1,281 @@
+use std::time::Duration;
+
+use reqwest::{
+    header::{HeaderMap, LINK, RETRY_AFTER},
+    StatusCode,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::error::{Error, ProviderErrorKind};
+
+pub const GITHUB_API_URL: &str = "https://api.github.com";
+
+/// Pages followed through `Link` headers before a list is cut short
+const MAX_PAGES: usize = 10;
+/// Longest `Retry-After` waited out instead of failing, Lambda timeouts are short
+const MAX_RETRY_WAIT: Duration = Duration::from_secs(1);
+
+/// `GET /user`, `id` stays the same when the login or emails change
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct GithubUser {
+    pub id: u64,
+    pub login: String,
+    pub name: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+/// An entry of `GET /user/emails`
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct GithubUserEmail {
+    pub email: String,
+    pub primary: bool,
+    pub verified: bool,
+}
+
+/// An entry of `GET /user/orgs`
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct GithubOrg {
+    pub id: u64,
+    pub login: String,
+}
+
+/// An entry of `GET /user/teams`
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct GithubTeam {
+    pub id: u64,
+    pub slug: String,
+    pub organization: GithubOrg,
+}
+
+#[derive(Debug, Clone)]
+struct Page {
+    body: String,
+    next: Option<String>,
+}
+
+/// Typed calls to the GitHub REST API on behalf of a user's access token.
+///
+/// Lists follow `Link: rel="next"` pages and a short `Retry-After` is waited out once.
+#[derive(Debug, Clone)]
+pub struct GithubClient {
+    http: reqwest::Client,
+    api_url: String,
+}
+
+impl GithubClient {
+    pub fn new(http: reqwest::Client, api_url: &str) -> Self {
+        Self {
+            http,
+            api_url: api_url.trim_end_matches('/').to_owned(),
+        }
+    }
+
+    pub fn api_url(&self) -> &str {
+        &self.api_url
+    }
+
+    /// The underlying HTTP client, e.g. for the token exchange
+    pub fn http(&self) -> &reqwest::Client {
+        &self.http
+    }
+
+    pub async fn user(&self, token: &str) -> Result<GithubUser, Error> {
+        let page = self.fetch(token, &format!("{}/user", self.api_url)).await?;
+        parse(&page.body)
+    }
+
+    pub async fn emails(&self, token: &str) -> Result<Vec<GithubUserEmail>, Error> {
+        self.list(token, "/user/emails").await
+    }
+
+    /// Needs the `read:org` scope to see private memberships
+    pub async fn orgs(&self, token: &str) -> Result<Vec<GithubOrg>, Error> {
+        self.list(token, "/user/orgs").await
+    }
+
+    /// Needs the `read:org` scope
+    pub async fn teams(&self, token: &str) -> Result<Vec<GithubTeam>, Error> {
+        self.list(token, "/user/teams").await
+    }
+
+    async fn list<T: DeserializeOwned>(&self, token: &str, path: &str) -> Result<Vec<T>, Error> {
+        let mut url = format!("{}{path}?per_page=100", self.api_url);
+        let mut items = vec![];
+        for _ in 0..MAX_PAGES {
+            let page = self.fetch(token, &url).await?;
+            items.extend(parse::<Vec<T>>(&page.body)?);
+            match page.next {
+                Some(next) if is_under(&self.api_url, &next) => url = next,
+                Some(next) => {
+                    // the token must never leave the API
+                    return Err(Error::provider(
+                        ProviderErrorKind::Api,
+                        format!("GitHub sent a next page outside {}: {next}", self.api_url),
+                    ));
+                }
+                None => return Ok(items),
+            }
+        }
+        tracing::warn!("stopped listing `{}` after {} pages", path, MAX_PAGES);
+        Ok(items)
+    }
+
+    async fn fetch(&self, token: &str, url: &str) -> Result<Page, Error> {
+        let mut waited = false;
+        loop {
+            let res = self
+                .http
+                .get(url)
+                .bearer_auth(token)
+                .header("Accept", "application/vnd.github+json")
+                .header("X-GitHub-Api-Version", "2022-11-28")
+                .send()
+                .await?;
+
+            if let Some(wait) = rate_limited(res.status(), res.headers(), crate::session::now()) {
+                if !waited && wait <= MAX_RETRY_WAIT {
+                    tracing::info!("rate limited by GitHub, retrying in {:?}", wait);
+                    tokio::time::sleep(wait).await;
+                    waited = true;
+                    continue;
+                }
+                return Err(Error::provider(
+                    ProviderErrorKind::Api,
+                    format!(
+                        "GitHub is rate limiting us, please try again in {} seconds.",
+                        wait.as_secs().max(1)
+                    ),
+                ));
+            }
+
+            let res = res.error_for_status()?;
+            let next = next_link(res.headers());
+            return Ok(Page {
+                body: res.text().await?,
+                next,
+            });
+        }
+    }
+}
+
+fn parse<T: DeserializeOwned>(body: &str) -> Result<T, Error> {
+    serde_json::from_str(body).map_err(|err| {
+        Error::provider(ProviderErrorKind::Api, "unexpected GitHub API response").with_source(err)
+    })
+}
+
+/// The `rel="next"` URL of a `Link` header
+fn next_link(headers: &HeaderMap) -> Option<String> {
+    let link = headers.get(LINK)?.to_str().ok()?;
+    link.split(',').find_map(|part| {
+        let (url, params) = part.split_once(';')?;
+        let is_next = params
+            .split(';')
+            .any(|param| param.trim().replace(' ', "") == "rel=\"next\"");
+        is_next.then(|| {
+            url.trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_owned()
+        })
+    })
+}
+
+/// Whether `url` is `base` or a path below it, `base` without a trailing `/`
+fn is_under(base: &str, url: &str) -> bool {
+    url.strip_prefix(base)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with(['/', '?']))
+}
+
+/// How long to wait when GitHub rate limits a response, by `Retry-After` or else by the
+/// primary limit's `x-ratelimit-reset`
+fn rate_limited(status: StatusCode, headers: &HeaderMap, now: i64) -> Option<Duration> {
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+
+    if let Some(secs) = header(RETRY_AFTER.as_str()).and_then(|value| value.parse::<u64>().ok()) {
+        return Some(Duration::from_secs(secs));
+    }
+    match header("x-ratelimit-remaining") {
+        Some("0") => {
+            let reset = header("x-ratelimit-reset")
+                .and_then(|value| value.parse::<i64>().ok())
+                .unwrap_or(now + 60);
+            Some(Duration::from_secs((reset - now).max(1) as u64))
+        }
+        // a plain 403, e.g. a missing scope
+        _ if status == StatusCode::FORBIDDEN => None,
+        _ => Some(Duration::from_secs(60)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.parse().unwrap(), HeaderValue::from_static(value)))
+            .collect()
+    }
+
+    #[test]
+    fn follows_next_links() {
+        let link = headers(&[(
+            "link",
+            "<https://api.github.com/user/orgs?page=1>; rel=\"prev\", \
+             <https://api.github.com/user/orgs?page=3>; rel=\"next\", \
+             <https://api.github.com/user/orgs?page=5>; rel=\"last\"",
+        )]);
+        assert_eq!(
+            next_link(&link).as_deref(),
+            Some("https://api.github.com/user/orgs?page=3")
+        );
+
+        let last = headers(&[(
+            "link",
+            "<https://api.github.com/user/orgs?page=1>; rel=\"first\"",
+        )]);
+        assert_eq!(next_link(&last), None);
+        assert_eq!(next_link(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn keeps_pages_under_the_api() {
+        let api = "https://ghe.example.com/api/v3";
+        assert!(is_under(api, "https://ghe.example.com/api/v3/user/orgs?page=2"));
+        assert!(!is_under(api, "https://ghe.example.com/api/v3.evil.com/user/orgs"));
+        assert!(!is_under(api, "https://evil.example.com/api/v3/user/orgs"));
+        assert!(!is_under(api, "https://ghe.example.com/api/user/orgs"));
+    }
+
+    #[test]
+    fn reads_rate_limits() {
+        let retry = headers(&[("retry-after", "30")]);
+        assert_eq!(
+            rate_limited(StatusCode::FORBIDDEN, &retry, 0),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(rate_limited(StatusCode::OK, &retry, 0), None);
+
+        let exhausted = headers(&[
+            ("x-ratelimit-remaining", "0"),
+            ("x-ratelimit-reset", "1010"),
+        ]);
+        assert_eq!(
+            rate_limited(StatusCode::FORBIDDEN, &exhausted, 1000),
+            Some(Duration::from_secs(10))
+        );
+
+        let forbidden = headers(&[("x-ratelimit-remaining", "4999")]);
+        assert_eq!(rate_limited(StatusCode::FORBIDDEN, &forbidden, 0), None);
+        assert_eq!(
+            rate_limited(StatusCode::TOO_MANY_REQUESTS, &forbidden, 0),
+            Some(Duration::from_secs(60))
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use super::{GithubUser, GithubUserEmail};
+use crate::error::Error;
+
+/// Which of a GitHub account's emails it signs in with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmailAcceptance {
+    /// Only the verified primary email, or one in the preferred domains
+    #[default]
+    Primary,
+    /// Any verified email, the primary one first
+    Verified,
+    /// Any verified email, else the account's `noreply` address
+    Noreply,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EmailPolicy {
+    pub acceptance: EmailAcceptance,
+    /// Domains whose verified emails win over the primary one, lowercase, in order
+    pub preferred_domains: Vec<String>,
+}
+
+/// The email a user signs in with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChosenEmail {
+    pub email: String,
+    /// `false` for the `noreply` fallback
+    pub verified: bool,
+}
+
+impl EmailPolicy {
+    /// `<prefix>_EMAIL_POLICY` (`primary`, `verified` or `noreply`) and the comma separated
+    /// `<prefix>_EMAIL_DOMAINS`
+    pub fn from_env(prefix: &str) -> Result<Self, Error> {
+        let var = |name: &str| std::env::var(format!("{prefix}_{name}")).unwrap_or_default();
+        let acceptance = match var("EMAIL_POLICY").trim() {
+            "" | "primary" => EmailAcceptance::Primary,
+            "verified" => EmailAcceptance::Verified,
+            "noreply" => EmailAcceptance::Noreply,
+            other => {
+                return Err(Error::config(format!(
+                    "{prefix}_EMAIL_POLICY `{other}` is not primary, verified or noreply"
+                )))
+            }
+        };
+        let preferred_domains = var("EMAIL_DOMAINS")
+            .split(',')
+            .map(|domain| domain.trim().trim_start_matches('@').to_lowercase())
+            .filter(|domain| !domain.is_empty())
+            .collect();
+        Ok(Self {
+            acceptance,
+            preferred_domains,
+        })
+    }
+
+    /// Pick the email, `None` when the account has none the policy accepts.
+    ///
+    /// `noreply_domain` is the instance's, e.g. `users.noreply.github.com`.
+    pub fn choose(
+        &self,
+        user: &GithubUser,
+        emails: &[GithubUserEmail],
+        noreply_domain: &str,
+    ) -> Option<ChosenEmail> {
+        let mut candidates = emails
+            .iter()
+            .filter(|email| email.verified)
+            .filter(|email| {
+                email.primary
+                    || self.acceptance != EmailAcceptance::Primary
+                    || self.is_preferred(&email.email)
+            })
+            .collect::<Vec<_>>();
+        // stable, so GitHub's order breaks ties
+        candidates.sort_by_key(|email| (self.domain_rank(&email.email), !email.primary));
+
+        match candidates.first() {
+            Some(email) => Some(ChosenEmail {
+                email: email.email.clone(),
+                verified: true,
+            }),
+            None if self.acceptance == EmailAcceptance::Noreply => Some(ChosenEmail {
+                email: format!("{}+{}@{noreply_domain}", user.id, user.login),
+                verified: false,
+            }),
+            None => None,
+        }
+    }
+
+    /// The index of the email's domain among the preferred ones, after them when absent
+    fn domain_rank(&self, email: &str) -> usize {
+        let domain = email
+            .rsplit_once('@')
+            .map(|(_, domain)| domain.to_lowercase())
+            .unwrap_or_default();
+        self.preferred_domains
+            .iter()
+            .position(|preferred| *preferred == domain)
+            .unwrap_or(self.preferred_domains.len())
+    }
+
+    fn is_preferred(&self, email: &str) -> bool {
+        self.domain_rank(email) < self.preferred_domains.len()
+    }
+
+    /// What the account lacks, for the page explaining a refused sign in
+    pub fn requirement(&self) -> &'static str {
+        match self.acceptance {
+            EmailAcceptance::Primary => "a verified primary email address",
+            EmailAcceptance::Verified | EmailAcceptance::Noreply => "a verified email address",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user() -> GithubUser {
+        GithubUser {
+            id: 1234,
+            login: String::from("octo"),
+            name: None,
+            avatar_url: None,
+        }
+    }
+
+    fn email(email: &str, primary: bool, verified: bool) -> GithubUserEmail {
+        GithubUserEmail {
+            email: email.to_owned(),
+            primary,
+            verified,
+        }
+    }
+
+    fn chosen(policy: &EmailPolicy, emails: &[GithubUserEmail]) -> Option<String> {
+        policy
+            .choose(&user(), emails, "users.noreply.github.com")
+            .map(|chosen| chosen.email)
+    }
+
+    #[test]
+    fn chooses_by_policy() {
+        let emails = [
+            email("unverified@acme.com", false, false),
+            email("octo@gmail.com", true, true),
+            email("octo@acme.com", false, true),
+        ];
+        let primary = EmailPolicy::default();
+        assert_eq!(chosen(&primary, &emails).as_deref(), Some("octo@gmail.com"));
+
+        let primary_preferring = EmailPolicy {
+            preferred_domains: vec![String::from("acme.com")],
+            ..EmailPolicy::default()
+        };
+        assert_eq!(
+            chosen(&primary_preferring, &emails).as_deref(),
+            Some("octo@acme.com")
+        );
+
+        let preferring = EmailPolicy {
+            acceptance: EmailAcceptance::Verified,
+            preferred_domains: vec![String::from("acme.com")],
+        };
+        assert_eq!(
+            chosen(&preferring, &emails).as_deref(),
+            Some("octo@acme.com")
+        );
+
+        // the primary email is not verified
+        let emails = [
+            email("octo@gmail.com", true, false),
+            email("octo@example.com", false, true),
+        ];
+        assert_eq!(chosen(&primary, &emails), None);
+        assert_eq!(chosen(&primary_preferring, &emails), None);
+        assert_eq!(
+            chosen(&preferring, &emails).as_deref(),
+            Some("octo@example.com")
+        );
+    }
+
+    #[test]
+    fn falls_back_to_noreply() {
+        let policy = EmailPolicy {
+            acceptance: EmailAcceptance::Noreply,
+            ..EmailPolicy::default()
+        };
+        let fallback = policy
+            .choose(
+                &user(),
+                &[email("octo@gmail.com", true, false)],
+                "users.noreply.github.com",
+            )
+            .unwrap();
+        assert_eq!(fallback.email, "1234+octo@users.noreply.github.com");
+        assert!(!fallback.verified);
+    }
+}
@@ -0,0 +1,465 @@
+mod client;
+mod email;
+mod policy;
+mod provider;
+
+pub use client::{
+    GithubClient, GithubOrg, GithubTeam, GithubUser, GithubUserEmail, GITHUB_API_URL,
+};
+pub use email::{ChosenEmail, EmailAcceptance, EmailPolicy};
+pub use policy::{Membership, MembershipPolicy};
+pub use provider::GithubProvider;
+
+use std::time::Duration;
+
+use crate::{
+    helpers::{constant_time_eq, find_cookie, Cookie, ResponseBuilder},
+    model::{User, COOKIE_NAME},
+    secrets::SecretProvider,
+    session::{now, session_user},
+    oauth::Scopes,
+    signin::{Decision, SignInPolicy},
+    templates::{render, AccessDeniedPage, Branding, NoEmailPage},
+    tokens::{ProviderToken, TokenVault},
+    users::{self, AccountLinking, ProviderProfile, UserStore},
+};
+
+use super::super::error::{Error, ProviderErrorKind};
+use async_session::{Session, SessionStore};
+use aws_lambda_events::apigw::{
+    ApiGatewayV2httpRequest as Request, ApiGatewayV2httpResponse as Response,
+};
+use oauth2::{
+    basic::{BasicClient, BasicErrorResponse},
+    reqwest::async_http_client,
+    AuthType, AuthUrl, AuthorizationCode, ClientId, ClientSecret, RedirectUrl, RefreshToken,
+    RequestTokenError, TokenResponse, TokenUrl,
+};
+use oauth2::{CsrfToken, Scope};
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use tracing::info;
+
+/// github.com's endpoints, Enterprise Server instances derive theirs from their host
+pub const GITHUB_AUTH_URL: &str = "https://github.com/login/oauth/authorize";
+pub const GITHUB_TOKEN_URL: &str = "https://github.com/login/oauth/access_token";
+
+const SESSION_TTL: Duration = Duration::from_secs(604800);
+/// Holds the OAuth `state` between /start and the callback
+const STATE_COOKIE: &str = "OAUTH_STATE";
+const STATE_TTL: Duration = Duration::from_secs(600);
+
+pub fn oauth_client(
+    client_id: String,
+    client_secret: String,
+    auth_url: String,
+    token_url: String,
+    redirect_url: String,
+) -> Result<BasicClient, Error> {
+    let invalid = |name: &str, err| Error::config(format!("invalid {name}")).with_source(err);
+    Ok(BasicClient::new(
+        ClientId::new(client_id),
+        Some(ClientSecret::new(client_secret)),
+        AuthUrl::new(auth_url).map_err(|err| invalid("OAuth authorize URL", err))?,
+        Some(TokenUrl::new(token_url).map_err(|err| invalid("OAuth token URL", err))?),
+    )
+    .set_redirect_uri(RedirectUrl::new(redirect_url).map_err(|err| invalid("callback URL", err))?))
+}
+
+/// The OAuth app's client id and secret, named by the provider's parameters
+async fn client_credentials(
+    provider: &GithubProvider,
+    secrets: &SecretProvider,
+) -> Result<(String, String), Error> {
+    let mut values = secrets
+        .get_many(&[&provider.client_id_param, &provider.client_secret_param])
+        .await?
+        .into_iter();
+    match (values.next(), values.next()) {
+        (Some(client_id), Some(client_secret)) => Ok((client_id, client_secret)),
+        _ => Err(Error::Secret {
+            name: format!("{} client credentials", provider.name),
+            source: None,
+        }),
+    }
+}
+
+/// The OAuth client for code and refresh token exchanges, sending the app's credentials in
+/// the request body as GitHub expects
+async fn token_client(
+    provider: &GithubProvider,
+    secrets: &SecretProvider,
+) -> Result<BasicClient, Error> {
+    let (client_id, client_secret) = client_credentials(provider, secrets).await?;
+    let invalid = |err| {
+        Error::config(format!("{} has an invalid OAuth endpoint", provider.name)).with_source(err)
+    };
+    Ok(BasicClient::new(
+        ClientId::new(client_id),
+        Some(ClientSecret::new(client_secret)),
+        AuthUrl::new(provider.auth_url.clone()).map_err(invalid)?,
+        Some(TokenUrl::new(provider.token_url.clone()).map_err(invalid)?),
+    )
+    .set_auth_type(AuthType::RequestBody))
+}
+
+/// The OAuth error code when the provider refused the grant, rather than failing to answer.
+/// GitHub refuses with a 200 and the error in the body, which `oauth2` fails to parse.
+fn refused_grant<RE: std::error::Error + 'static>(
+    err: &RequestTokenError<RE, BasicErrorResponse>,
+) -> Option<String> {
+    match err {
+        RequestTokenError::ServerResponse(response) => Some(response.error().to_string()),
+        RequestTokenError::Parse(_, body) => serde_json::from_slice::<BasicErrorResponse>(body)
+            .ok()
+            .map(|response| response.error().to_string()),
+        _ => None,
+    }
+}
+
+fn token_error<RE: std::error::Error + Send + Sync + 'static>(
+    err: RequestTokenError<RE, BasicErrorResponse>,
+) -> Error {
+    let message = match refused_grant(&err) {
+        Some(code) => format!("the provider refused the grant: {code}"),
+        None => String::from("token request failed"),
+    };
+    Error::provider(ProviderErrorKind::TokenExchange, message).with_source(err)
+}
+
+/// Redirect to the provider's consent screen, which sends the user back to `callback_url`
+/// with the `state` kept in a cookie for the callback to check
+pub async fn oauth_redirect(
+    provider: &GithubProvider,
+    secrets: &SecretProvider,
+    callback_url: &str,
+) -> Result<Response, Error> {
+    info!("callback url: {}", callback_url);
+
+    let (client_id, client_secret) = client_credentials(provider, secrets).await?;
+
+    let oc = oauth_client(
+        client_id,
+        client_secret,
+        provider.auth_url.clone(),
+        provider.token_url.clone(),
+        callback_url.to_string(),
+    )?;
+
+    let (auth_url, csrf_token) = oc
+        .authorize_url(CsrfToken::new_random)
+        .add_scopes(
+            provider
+                .requested_scopes()
+                .iter()
+                .map(|scope| Scope::new(scope.to_string())),
+        )
+        .url();
+
+    ResponseBuilder::new(307)
+        .set_cookie(&Cookie::new(STATE_COOKIE, csrf_token.secret()).max_age(STATE_TTL))
+        .redirect(auth_url.as_str())
+        .build()
+}
+
+/// The narrower scopes a GitHub scope grants, which GitHub does not list separately
+pub fn implied_scopes(scope: &str) -> &'static [&'static str] {
+    match scope {
+        "user" => &["read:user", "user:email", "user:follow"],
+        "admin:org" => &["write:org", "read:org"],
+        "write:org" => &["read:org"],
+        "repo" => &["repo:status", "repo_deployment", "public_repo", "repo:invite"],
+        _ => &[],
+    }
+}
+
+/// What a login callback needs besides its provider, the same for every provider
+pub struct CallbackContext<'a, S, U> {
+    pub secrets: &'a SecretProvider,
+    pub session_store: &'a S,
+    pub user_store: &'a U,
+    pub sign_in: &'a SignInPolicy,
+    /// Keeps the provider tokens for calls on the user's behalf
+    pub tokens: &'a TokenVault,
+}
+
+/// Finish the login, recording the user in `user_store`, and redirect to `landing_url`
+/// with the new session. Signing in while signed in links the GitHub account to that user.
+///
+/// Accounts outside the orgs and teams of the provider's policy, or without an email its
+/// `EmailPolicy` accepts, get a page explaining why that links back to `login_url`. Emails
+/// the sign in rules deny are refused, and so are callbacks whose `state` is not the one
+/// /start set, before anything is exchanged or linked.
+pub async fn oauth_callback<S: SessionStore, U: UserStore>(
+    provider: &GithubProvider,
+    ctx: &CallbackContext<'_, S, U>,
+    request: &Request,
+    landing_url: &str,
+    login_url: &str,
+) -> Result<Response, Error> {
+    let CallbackContext {
+        secrets,
+        session_store,
+        user_store,
+        sign_in,
+        tokens,
+    } = ctx;
+    let policy = &provider.policy;
+    let github = &provider.api;
+
+    let code = request
+        .query_string_parameters
+        .first("code")
+        .ok_or(Error::BadRequest(String::from(
+            "The login request is missing the `code` parameter.",
+        )))?
+        .to_owned();
+    let state = request
+        .query_string_parameters
+        .first("state")
+        .ok_or(Error::BadRequest(String::from(
+            "The login request is missing the `state` parameter.",
+        )))?;
+    let cookies = request.cookies.as_deref().unwrap_or_default();
+    let expected = find_cookie(cookies, STATE_COOKIE).unwrap_or_default();
+    if expected.is_empty() || !constant_time_eq(expected.as_bytes(), state.as_bytes()) {
+        info!("login callback state does not match the one /start set");
+        return Err(Error::BadRequest(String::from(
+            "The login request expired or did not start here. Please sign in again.",
+        )));
+    }
+
+    let response = token_client(provider, secrets)
+        .await?
+        .exchange_code(AuthorizationCode::new(code))
+        .request_async(async_http_client)
+        .await
+        .map_err(token_error)?;
+    let access_token = response.access_token().secret();
+    let required = provider.requested_scopes();
+    let scopes = Scopes::granted(&response, &required);
+
+    let granted = scopes.clone().with_implied(implied_scopes);
+    if let Some(missing) = granted.missing(&required).first() {
+        tracing::info!("granted scope `{}`, missing `{}`", scopes, missing);
+        return Err(Error::provider(
+            ProviderErrorKind::Scope,
+            match *missing {
+                "user:email" => {
+                    String::from("Access to your email address is required to sign in.")
+                }
+                "read:org" => String::from(
+                    "Access to your organization memberships is required to sign in.",
+                ),
+                scope => format!("The `{scope}` permission is required to sign in."),
+            },
+        ));
+    }
+
+    let github_user = github.user(access_token).await?;
+
+    let membership = policy
+        .membership(github, access_token)
+        .await?;
+    if !policy.allows(&membership) {
+        info!("denied `{}`, member of {:?}", github_user.login, membership);
+        let page = render(&AccessDeniedPage {
+            branding: Branding::global(),
+            provider: &provider.label,
+            login: &github_user.login,
+            requirement: &policy.describe(),
+            login_url: login_url.to_owned(),
+        })?;
+        return ResponseBuilder::new(403).html(page).build();
+    }
+
+    let user_emails = github.emails(access_token).await?;
+
+    let Some(ChosenEmail { email, verified }) =
+        provider
+            .emails
+            .choose(&github_user, &user_emails, &provider.noreply_domain())
+    else {
+        info!("`{}` has no acceptable email", github_user.login);
+        let page = render(&NoEmailPage {
+            branding: Branding::global(),
+            provider: &provider.label,
+            login: &github_user.login,
+            requirement: provider.emails.requirement(),
+            settings_url: format!("{}/settings/emails", provider.web_url),
+            login_url: login_url.to_owned(),
+        })?;
+        return ResponseBuilder::new(403).html(page).build();
+    };
+
+    if let Decision::Deny(_) = sign_in.evaluate(&email).await? {
+        return Err(Error::Forbidden(format!(
+            "{email} is not allowed to sign in here. Ask an administrator for access."
+        )));
+    }
+
+    let signed_in = match find_cookie(cookies, COOKIE_NAME) {
+        Some(cookie) => session_store
+            .load_session(cookie.to_string())
+            .await?
+            .and_then(|session| session_user(&session)),
+        None => None,
+    };
+
+    let subject = github_user.id.to_string();
+    let record = users::sign_in(
+        *user_store,
+        &ProviderProfile {
+            provider: provider.name.clone(),
+            subject: subject.clone(),
+            login: Some(github_user.login),
+            name: github_user.name,
+            email: Some(email.clone()),
+            email_verified: verified,
+            avatar_url: github_user.avatar_url,
+        },
+        signed_in.as_deref(),
+        AccountLinking::from_env(),
+    )
+    .await?;
+
+    let token = ProviderToken::from_response(&provider.name, &subject, &response, &scopes);
+    tokens.put(&record.id, &token).await?;
+    let grant = match (provider.session_follows_grant, tokens.is_enabled()) {
+        (true, true) => Some(token.identity()),
+        (true, false) => {
+            tracing::warn!("sessions cannot follow {} grants without a vault", provider.name);
+            None
+        }
+        (false, _) => None,
+    };
+
+    let user = User {
+        id: record.id,
+        email,
+        orgs: membership.orgs,
+        teams: membership.teams,
+        scopes,
+        identity: Some(token.identity()),
+        grant,
+    };
+    let mut session = Session::new();
+    session
+        .insert("user", user)
+        .map_err(|err| Error::session("failed to serialize session").with_source(err))?;
+    session.expire_in(SESSION_TTL);
+
+    let Some(cookie) = session_store.store_session(session).await? else {
+        return Err(Error::session("store returned no cookie value"));
+    };
+
+    let route = format!(
+        "{landing_url}?session={}",
+        utf8_percent_encode(&cookie, NON_ALPHANUMERIC)
+    );
+    ResponseBuilder::new(307)
+        .set_cookie(&Cookie::new(COOKIE_NAME, &cookie).max_age(SESSION_TTL))
+        .set_cookie(&Cookie::removal(STATE_COOKIE))
+        .redirect(&route)
+        .build()
+}
+
+/// A current access token for the user's account at `provider`, to call it on their behalf.
+///
+/// An expired token is refreshed and kept; without a token, or when the provider refuses the
+/// refresh, the user has to sign in again. A refused refresh means the grant was revoked, so
+/// its token is deleted, which also ends the sessions that follow the grant.
+pub async fn access_token(
+    provider: &GithubProvider,
+    secrets: &SecretProvider,
+    tokens: &TokenVault,
+    user_id: &str,
+) -> Result<String, Error> {
+    let sign_in_again = || {
+        Error::Unauthorized(format!(
+            "Sign in with {} again to continue.",
+            provider.label
+        ))
+    };
+    let Some(token) = tokens.token(user_id, &provider.name).await? else {
+        return Err(sign_in_again());
+    };
+    if !token.is_expired(now()) {
+        return Ok(token.access_token);
+    }
+    let Some(refresh_token) = token.refresh_token.clone() else {
+        return Err(sign_in_again());
+    };
+
+    let response = token_client(provider, secrets)
+        .await?
+        .exchange_refresh_token(&RefreshToken::new(refresh_token))
+        .request_async(async_http_client)
+        .await;
+    let token = match response {
+        Ok(response) => token.refreshed(&response),
+        Err(err) => {
+            let Some(code) = refused_grant(&err) else {
+                return Err(token_error(err));
+            };
+            info!("the grant of `{}` is gone: {}", token.identity(), code);
+            tokens.delete(user_id, &token.identity()).await?;
+            return Err(sign_in_again());
+        }
+    };
+    tokens.put(user_id, &token).await?;
+    Ok(token.access_token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{session::MemorySessionStore, users::MemoryUserStore};
+    use aws_lambda_events::query_map::QueryMap;
+    use std::collections::HashMap;
+
+    fn callback(state: Option<&str>, cookie: Option<&str>) -> Request {
+        let mut query = HashMap::from([(String::from("code"), vec![String::from("code")])]);
+        if let Some(state) = state {
+            query.insert(String::from("state"), vec![state.to_owned()]);
+        }
+        Request {
+            query_string_parameters: QueryMap::from(query),
+            cookies: cookie.map(|cookie| vec![format!("{STATE_COOKIE}={cookie}")]),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_callbacks_without_the_started_state() {
+        let provider = GithubProvider::github_com(
+            "github",
+            reqwest::Client::new(),
+            "/oath/github/client_id",
+            "/oath/github/client_secret",
+        );
+        let ctx = CallbackContext {
+            secrets: &SecretProvider::Static(HashMap::new()),
+            session_store: &MemorySessionStore::new(),
+            user_store: &MemoryUserStore::new(),
+            sign_in: &SignInPolicy::default(),
+            tokens: &TokenVault::default(),
+        };
+        let code = |request: Request| {
+            let ctx = &ctx;
+            let provider = &provider;
+            async move {
+                oauth_callback(provider, ctx, &request, "/protected", "/login")
+                    .await
+                    .unwrap_err()
+                    .code()
+            }
+        };
+
+        assert_eq!(code(callback(None, Some("abc"))).await, "bad_request");
+        assert_eq!(code(callback(Some("abc"), None)).await, "bad_request");
+        assert_eq!(code(callback(Some("abc"), Some("abd"))).await, "bad_request");
+        assert_eq!(code(callback(Some(""), Some(""))).await, "bad_request");
+        // a matching state gets as far as the app's credentials
+        assert_eq!(code(callback(Some("abc"), Some("abc"))).await, "secret");
+    }
+}
@@ -0,0 +1,141 @@
+use serde::{Deserialize, Serialize};
+
+use super::GithubClient;
+use crate::error::Error;
+
+/// Which GitHub orgs and teams may sign in, anyone when both are empty
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MembershipPolicy {
+    /// Org logins, lowercase
+    pub orgs: Vec<String>,
+    /// `<org>/<team slug>`, lowercase
+    pub teams: Vec<String>,
+}
+
+/// The orgs and teams a user belongs to, as far as the policy needed to look
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Membership {
+    pub orgs: Vec<String>,
+    pub teams: Vec<String>,
+}
+
+impl MembershipPolicy {
+    /// Comma separated lists of orgs and of `<org>/<team>` slugs
+    pub fn parse(orgs: &str, teams: &str) -> Result<Self, Error> {
+        let list = |value: &str| {
+            value
+                .split(',')
+                .map(|item| item.trim().to_lowercase())
+                .filter(|item| !item.is_empty())
+                .collect::<Vec<_>>()
+        };
+        let teams = list(teams);
+        let is_team = |team: &&String| match team.split_once('/') {
+            Some((org, slug)) => !org.is_empty() && !slug.is_empty(),
+            None => false,
+        };
+        if let Some(team) = teams.iter().find(|team| !is_team(team)) {
+            return Err(Error::config(format!(
+                "allowed team `{team}` is not `<org>/<team>`"
+            )));
+        }
+        Ok(Self {
+            orgs: list(orgs),
+            teams,
+        })
+    }
+
+    /// `<prefix>_ALLOWED_ORGS` and `<prefix>_ALLOWED_TEAMS`, e.g. `GITHUB_ALLOWED_ORGS`
+    pub fn from_env(prefix: &str) -> Result<Self, Error> {
+        let var = |name: &str| std::env::var(format!("{prefix}_{name}")).unwrap_or_default();
+        Self::parse(&var("ALLOWED_ORGS"), &var("ALLOWED_TEAMS"))
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.orgs.is_empty() && self.teams.is_empty()
+    }
+
+    /// Private memberships are only visible with `read:org`
+    pub fn needs_org_scope(&self) -> bool {
+        !self.is_open()
+    }
+
+    pub fn allows(&self, membership: &Membership) -> bool {
+        self.is_open()
+            || self.orgs.iter().any(|org| membership.orgs.contains(org))
+            || self
+                .teams
+                .iter()
+                .any(|team| membership.teams.contains(team))
+    }
+
+    /// Who may sign in, for the denial page
+    pub fn describe(&self) -> String {
+        let orgs = self
+            .orgs
+            .iter()
+            .map(|org| format!("the {org} organization"));
+        let teams = self.teams.iter().map(|team| format!("the {team} team"));
+        orgs.chain(teams).collect::<Vec<_>>().join(" or ")
+    }
+
+    /// Look up the user's orgs, and teams when the policy names any
+    pub async fn membership(
+        &self,
+        github: &GithubClient,
+        token: &str,
+    ) -> Result<Membership, Error> {
+        if self.is_open() {
+            return Ok(Membership::default());
+        }
+        let orgs = github
+            .orgs(token)
+            .await?
+            .into_iter()
+            .map(|org| org.login.to_lowercase())
+            .collect();
+        let teams = match self.teams.is_empty() {
+            true => vec![],
+            false => github
+                .teams(token)
+                .await?
+                .into_iter()
+                .map(|team| format!("{}/{}", team.organization.login, team.slug).to_lowercase())
+                .collect(),
+        };
+        Ok(Membership { orgs, teams })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_members() {
+        let policy = MembershipPolicy::parse("Acme, ", "other/Platform").unwrap();
+        assert_eq!(policy.orgs, ["acme"]);
+        assert_eq!(
+            policy.describe(),
+            "the acme organization or the other/platform team"
+        );
+
+        let member = |orgs: &[&str], teams: &[&str]| Membership {
+            orgs: orgs.iter().map(|org| org.to_string()).collect(),
+            teams: teams.iter().map(|team| team.to_string()).collect(),
+        };
+        assert!(policy.allows(&member(&["acme"], &[])));
+        assert!(policy.allows(&member(&["other"], &["other/platform"])));
+        assert!(!policy.allows(&member(&["other"], &["other/sre"])));
+
+        let open = MembershipPolicy::parse("", "").unwrap();
+        assert!(open.is_open());
+        assert!(open.allows(&Membership::default()));
+    }
+
+    #[test]
+    fn rejects_teams_without_org() {
+        assert!(MembershipPolicy::parse("", "platform").is_err());
+        assert!(MembershipPolicy::parse("", "acme/").is_err());
+    }
+}
@@ -0,0 +1,231 @@
+use super::{
+    EmailPolicy, GithubClient, MembershipPolicy, GITHUB_API_URL, GITHUB_AUTH_URL,
+    GITHUB_TOKEN_URL,
+};
+use crate::{error::Error, oauth::Scopes};
+
+/// What a login asks for unless `<PREFIX>_SCOPES` says otherwise
+pub const DEFAULT_SCOPES: &str = "user:email";
+
+/// One GitHub instance users sign in with, github.com or a GitHub Enterprise Server,
+/// registered under the provider name used in its routes and identities
+#[derive(Debug, Clone)]
+pub struct GithubProvider {
+    /// e.g. `github` in `/login/github/start` and `github:<numeric id>`
+    pub name: String,
+    /// Shown on the login page
+    pub label: String,
+    /// e.g. `https://github.com`, for links to the user's settings
+    pub web_url: String,
+    pub auth_url: String,
+    pub token_url: String,
+    pub api: GithubClient,
+    /// Parameter names of the OAuth app's client id and secret
+    pub client_id_param: String,
+    pub client_secret_param: String,
+    /// Requested, and required to be granted
+    pub scopes: Scopes,
+    pub policy: MembershipPolicy,
+    pub emails: EmailPolicy,
+    /// Sessions end once the grant is revoked at the provider, noticed when its token is
+    /// next refreshed
+    pub session_follows_grant: bool,
+}
+
+impl GithubProvider {
+    /// github.com, with the OAuth app in `client_id_param` and `client_secret_param`
+    pub fn github_com(
+        name: &str,
+        http: reqwest::Client,
+        client_id_param: &str,
+        client_secret_param: &str,
+    ) -> Self {
+        Self {
+            name: name.to_owned(),
+            label: String::from("GitHub"),
+            web_url: String::from("https://github.com"),
+            auth_url: String::from(GITHUB_AUTH_URL),
+            token_url: String::from(GITHUB_TOKEN_URL),
+            api: GithubClient::new(http, GITHUB_API_URL),
+            client_id_param: client_id_param.to_owned(),
+            client_secret_param: client_secret_param.to_owned(),
+            scopes: Scopes::parse(DEFAULT_SCOPES),
+            policy: MembershipPolicy::default(),
+            emails: EmailPolicy::default(),
+            session_follows_grant: false,
+        }
+    }
+
+    /// Where the instance's `noreply` addresses live, `users.noreply.<host>`
+    pub fn noreply_domain(&self) -> String {
+        let host = self
+            .web_url
+            .split_once("://")
+            .map_or(self.web_url.as_str(), |(_, host)| host);
+        format!("users.noreply.{host}")
+    }
+
+    /// The configured scopes, plus `read:org` when the membership policy needs it
+    pub fn requested_scopes(&self) -> Scopes {
+        let mut scopes = self.scopes.clone();
+        if self.policy.needs_org_scope() {
+            scopes.insert("read:org");
+        }
+        scopes
+    }
+
+    /// A GitHub Enterprise Server at `host`, e.g. `https://ghe.example.com`, with its
+    /// REST API under `/api/v3`
+    pub fn enterprise(
+        name: &str,
+        host: &str,
+        http: reqwest::Client,
+        client_id_param: &str,
+        client_secret_param: &str,
+    ) -> Self {
+        let host = host.trim_end_matches('/');
+        Self {
+            label: String::from("GitHub Enterprise"),
+            web_url: host.to_owned(),
+            auth_url: format!("{host}/login/oauth/authorize"),
+            token_url: format!("{host}/login/oauth/access_token"),
+            api: GithubClient::new(http.clone(), &format!("{host}/api/v3")),
+            ..Self::github_com(name, http, client_id_param, client_secret_param)
+        }
+    }
+
+    /// The provider `name`, configured by env vars prefixed with its uppercased name, e.g.
+    /// `GHE_` for `ghe`: `<PREFIX>_HOST` for an Enterprise Server (github.com when unset,
+    /// which only `github` may be), `<PREFIX>_API_URL`, `<PREFIX>_LABEL`,
+    /// `PARAM_<PREFIX>_CLIENT_ID`, `PARAM_<PREFIX>_CLIENT_SECRET` and
+    /// `<PREFIX>_SCOPES`, `<PREFIX>_ALLOWED_ORGS`/`_TEAMS` for `MembershipPolicy::from_env`
+    /// and `<PREFIX>_EMAIL_POLICY`/`_DOMAINS` for `EmailPolicy::from_env`, and
+    /// `<PREFIX>_SESSION_FOLLOWS_GRANT=true`
+    pub fn from_env(name: &str, http: reqwest::Client) -> Result<Self, Error> {
+        let prefix = name.to_uppercase().replace('-', "_");
+        let var = |suffix: &str| {
+            std::env::var(format!("{prefix}_{suffix}"))
+                .ok()
+                .filter(|value| !value.trim().is_empty())
+        };
+        let (Ok(client_id_param), Ok(client_secret_param)) = (
+            std::env::var(format!("PARAM_{prefix}_CLIENT_ID")),
+            std::env::var(format!("PARAM_{prefix}_CLIENT_SECRET")),
+        ) else {
+            return Err(Error::config(format!(
+                "PARAM_{prefix}_CLIENT_ID or PARAM_{prefix}_CLIENT_SECRET not set"
+            )));
+        };
+
+        let mut provider = match (var("HOST"), name) {
+            (Some(host), _) => {
+                Self::enterprise(name, &host, http, &client_id_param, &client_secret_param)
+            }
+            (None, "github") => {
+                Self::github_com(name, http, &client_id_param, &client_secret_param)
+            }
+            (None, _) => return Err(Error::config(format!("ENV VAR {prefix}_HOST not set"))),
+        };
+        if let Some(api_url) = var("API_URL") {
+            provider.api = GithubClient::new(provider.api.http().clone(), &api_url);
+        }
+        if let Some(label) = var("LABEL") {
+            provider.label = label;
+        }
+        if let Some(scopes) = var("SCOPES") {
+            provider.scopes = Scopes::parse(&scopes);
+        }
+        provider.policy = MembershipPolicy::from_env(&prefix)?;
+        provider.emails = EmailPolicy::from_env(&prefix)?;
+        provider.session_follows_grant = var("SESSION_FOLLOWS_GRANT").as_deref() == Some("true");
+        provider.validate()?;
+        Ok(provider)
+    }
+
+    /// Fail at start up on endpoints that are not absolute URLs, rather than on every login
+    pub fn validate(&self) -> Result<(), Error> {
+        let endpoints = [
+            ("web", self.web_url.as_str()),
+            ("authorize", &self.auth_url),
+            ("token", &self.token_url),
+            ("API", self.api.api_url()),
+        ];
+        for (endpoint, url) in endpoints {
+            reqwest::Url::parse(url).map_err(|err| {
+                Error::config(format!("{} has an invalid {endpoint} URL `{url}`", self.name))
+                    .with_source(err)
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Every provider named in `GITHUB_PROVIDERS`, `github` when unset
+    pub fn all_from_env(http: reqwest::Client) -> Result<Vec<Self>, Error> {
+        let names = std::env::var("GITHUB_PROVIDERS").unwrap_or_default();
+        let mut names = names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            names.push("github");
+        }
+        names
+            .into_iter()
+            .map(|name| Self::from_env(name, http.clone()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derives_enterprise_endpoints() {
+        let ghe = GithubProvider::enterprise(
+            "ghe",
+            "https://ghe.example.com/",
+            reqwest::Client::new(),
+            "/oath/ghe/client_id",
+            "/oath/ghe/client_secret",
+        );
+        assert_eq!(
+            ghe.auth_url,
+            "https://ghe.example.com/login/oauth/authorize"
+        );
+        assert_eq!(
+            ghe.token_url,
+            "https://ghe.example.com/login/oauth/access_token"
+        );
+        assert_eq!(ghe.api.api_url(), "https://ghe.example.com/api/v3");
+        assert_eq!(ghe.name, "ghe");
+        assert_eq!(ghe.noreply_domain(), "users.noreply.ghe.example.com");
+        assert_eq!(ghe.client_id_param, "/oath/ghe/client_id");
+    }
+
+    #[test]
+    fn configures_providers_by_name() {
+        std::env::set_var("PARAM_GHE_TEST_CLIENT_ID", "/oath/ghe/client_id");
+        std::env::set_var("PARAM_GHE_TEST_CLIENT_SECRET", "/oath/ghe/client_secret");
+        assert!(GithubProvider::from_env("ghe-test", reqwest::Client::new()).is_err());
+
+        std::env::set_var("GHE_TEST_HOST", "ghe.example.com");
+        assert!(GithubProvider::from_env("ghe-test", reqwest::Client::new()).is_err());
+
+        std::env::set_var("GHE_TEST_HOST", "https://ghe.example.com");
+        std::env::set_var("GHE_TEST_ALLOWED_ORGS", "acme");
+        let ghe = GithubProvider::from_env("ghe-test", reqwest::Client::new()).unwrap();
+        assert_eq!(ghe.api.api_url(), "https://ghe.example.com/api/v3");
+        assert_eq!(ghe.label, "GitHub Enterprise");
+        assert_eq!(ghe.policy.orgs, ["acme"]);
+        assert_eq!(ghe.requested_scopes(), Scopes::parse("user:email,read:org"));
+
+        std::env::set_var("GHE_TEST_SCOPES", "read:user user:email");
+        let ghe = GithubProvider::from_env("ghe-test", reqwest::Client::new()).unwrap();
+        assert_eq!(
+            ghe.requested_scopes(),
+            Scopes::parse("read:user,user:email,read:org")
+        );
+    }
+}
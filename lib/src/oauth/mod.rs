@@ -1 +1,4 @@
 pub mod github;
+mod scopes;
+
+pub use scopes::Scopes;
@@ -0,0 +1,132 @@
+use std::collections::BTreeSet;
+
+use oauth2::{TokenResponse, TokenType};
+use serde::{Deserialize, Serialize};
+
+/// A set of OAuth scopes, compared without regard to order or separators
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Scopes(BTreeSet<String>);
+
+impl Scopes {
+    /// Scopes separated by commas, as GitHub grants them, or by spaces, as RFC 6749 does
+    pub fn parse(scopes: &str) -> Self {
+        Self(
+            scopes
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|scope| !scope.is_empty())
+                .map(String::from)
+                .collect(),
+        )
+    }
+
+    /// What a token response granted, `scope` as GitHub or RFC 6749 separates it. A missing
+    /// `scope` means the `requested` ones (RFC 6749 5.1), an empty one that none were granted.
+    pub fn granted<TT: TokenType>(response: &impl TokenResponse<TT>, requested: &Scopes) -> Self {
+        match response.scopes() {
+            Some(scopes) => Self::parse(
+                &scopes
+                    .iter()
+                    .map(|scope| scope.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            None => requested.clone(),
+        }
+    }
+
+    pub fn contains(&self, scope: &str) -> bool {
+        self.0.contains(scope)
+    }
+
+    pub fn insert(&mut self, scope: &str) {
+        self.0.insert(scope.to_owned());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.0.iter().map(String::as_str)
+    }
+
+    /// Add the scopes each scope implies, e.g. GitHub's `user` grants `user:email`
+    pub fn with_implied(mut self, implied: impl Fn(&str) -> &'static [&'static str]) -> Self {
+        let extra = self
+            .iter()
+            .flat_map(|scope| implied(scope).iter().copied())
+            .map(String::from)
+            .collect::<Vec<_>>();
+        self.0.extend(extra);
+        self
+    }
+
+    /// The scopes of `required` this set lacks
+    pub fn missing<'a>(&self, required: &'a Scopes) -> Vec<&'a str> {
+        required.iter().filter(|scope| !self.contains(scope)).collect()
+    }
+}
+
+impl std::fmt::Display for Scopes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.iter().collect::<Vec<_>>().join(","))
+    }
+}
+
+impl<'a> FromIterator<&'a str> for Scopes {
+    fn from_iter<T: IntoIterator<Item = &'a str>>(iter: T) -> Self {
+        Self(iter.into_iter().map(String::from).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oauth2::basic::BasicTokenResponse;
+
+    #[test]
+    fn compares_as_sets() {
+        let granted = Scopes::parse("read:org, user:email");
+        let required = Scopes::parse("user:email read:org");
+        assert_eq!(granted, required);
+        assert!(granted.missing(&required).is_empty());
+        assert_eq!(granted.to_string(), "read:org,user:email");
+
+        let required = Scopes::parse("user:email,repo");
+        assert_eq!(granted.missing(&required), ["repo"]);
+        assert!(Scopes::parse(" , ").is_empty());
+    }
+
+    #[test]
+    fn grants_the_requested_scopes_when_scope_is_missing() {
+        let requested = Scopes::parse("user:email,read:org");
+        let response = |json: &str| serde_json::from_str::<BasicTokenResponse>(json).unwrap();
+
+        let listed = r#"{"access_token": "t", "token_type": "bearer", "scope": "repo,user"}"#;
+        let listed = response(listed);
+        assert_eq!(Scopes::granted(&listed, &requested), Scopes::parse("repo user"));
+
+        let empty = response(r#"{"access_token": "t", "token_type": "bearer", "scope": ""}"#);
+        // the user granted none, so the required ones are all missing
+        let none = Scopes::granted(&empty, &requested);
+        assert!(none.is_empty());
+        assert_eq!(none.missing(&requested).len(), 2);
+
+        let omitted = response(r#"{"access_token": "t", "token_type": "bearer"}"#);
+        assert_eq!(Scopes::granted(&omitted, &requested), requested);
+    }
+
+    #[test]
+    fn adds_implied_scopes() {
+        let implied = |scope: &str| -> &'static [&'static str] {
+            match scope {
+                "user" => &["user:email", "read:user"],
+                _ => &[],
+            }
+        };
+        let granted = Scopes::parse("user").with_implied(implied);
+        assert!(granted.contains("user:email"));
+        assert!(granted.missing(&Scopes::parse("user:email")).is_empty());
+    }
+}
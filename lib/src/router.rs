@@ -0,0 +1,578 @@
+use std::{collections::HashMap, future::Future, str::FromStr, sync::Arc};
+
+use aws_lambda_events::{
+    apigw::{ApiGatewayV2httpRequest as Request, ApiGatewayV2httpResponse as Response},
+    http::{header::ALLOW, HeaderValue, Method},
+};
+use futures::future::BoxFuture;
+use lambda_runtime::{Context, LambdaEvent};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    base_url::BaseUrl,
+    cors::CorsConfig,
+    error::Error,
+    helpers::{error_response, SecurityHeaders},
+};
+
+pub type HandlerResult = Result<Response, Error>;
+
+type Handler<S> = Arc<dyn Fn(RouteRequest<S>) -> BoxFuture<'static, HandlerResult> + Send + Sync>;
+
+/// Wraps a handler, call `next.run(req)` to continue down the chain
+pub type Middleware<S> =
+    Arc<dyn Fn(RouteRequest<S>, Next<S>) -> BoxFuture<'static, HandlerResult> + Send + Sync>;
+
+/// Build a [`Middleware`] from an async closure
+pub fn middleware<S, F, Fut>(f: F) -> Middleware<S>
+where
+    F: Fn(RouteRequest<S>, Next<S>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = HandlerResult> + Send + 'static,
+{
+    Arc::new(move |req, next| Box::pin(f(req, next)))
+}
+
+/// A matched request handed to handlers and middleware
+pub struct RouteRequest<S> {
+    pub request: Request,
+    pub context: Context,
+    pub state: Arc<S>,
+    params: HashMap<String, String>,
+    links: Arc<Links>,
+}
+
+/// What a handler needs to build absolute links back into the API
+#[derive(Debug, Clone)]
+struct Links {
+    base_url: BaseUrl,
+    named: HashMap<String, String>,
+}
+
+impl<S> RouteRequest<S> {
+    /// A path parameter captured by `{name}` in the route template
+    pub fn param<T: FromStr>(&self, name: &str) -> Result<T, Error> {
+        self.params
+            .get(name)
+            .and_then(|value| value.parse().ok())
+            .ok_or(Error::NotFound)
+    }
+
+    /// Deserialize the query string, e.g. into a struct with `code` and `state` fields
+    pub fn query<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        let raw = self.request.raw_query_string.as_deref().unwrap_or_default();
+        serde_urlencoded::from_str(raw)
+            .map_err(|err| Error::BadRequest(format!("Invalid query string: {err}")))
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.request
+            .headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    }
+
+    pub fn method(&self) -> &Method {
+        &self.request.request_context.http.method
+    }
+
+    /// Absolute URL of `path` under the public base URL
+    pub fn url(&self, path: &str) -> Result<String, Error> {
+        Ok(format!("{}{path}", self.links.base_url.resolve(&self.request)?))
+    }
+
+    /// Absolute URL of a named route, filling its `{param}`s from `params`
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Result<String, Error> {
+        let template = self
+            .links
+            .named
+            .get(name)
+            .ok_or(Error::internal(format!("no route named `{name}`")))?;
+        let path = split_path(template)
+            .map(|segment| {
+                match segment
+                    .strip_prefix('{')
+                    .and_then(|s| s.strip_suffix('}'))
+                {
+                    Some(param) => params
+                        .iter()
+                        .find(|(key, _)| *key == param)
+                        .map(|(_, value)| utf8_percent_encode(value, NON_ALPHANUMERIC).to_string())
+                        .ok_or(Error::internal(format!(
+                            "missing `{param}` for route `{name}`"
+                        ))),
+                    None => Ok(segment.to_owned()),
+                }
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        self.url(&format!("/{}", path.join("/")))
+    }
+}
+
+/// The rest of a middleware chain
+pub struct Next<S> {
+    middleware: Arc<[Middleware<S>]>,
+    handler: Handler<S>,
+}
+
+impl<S: Send + Sync + 'static> Next<S> {
+    pub fn run(self, req: RouteRequest<S>) -> BoxFuture<'static, HandlerResult> {
+        match self.middleware.split_first() {
+            Some((first, _)) => {
+                let first = first.clone();
+                let next = Next {
+                    middleware: self.middleware[1..].into(),
+                    handler: self.handler,
+                };
+                first(req, next)
+            }
+            None => (self.handler)(req),
+        }
+    }
+}
+
+pub struct Route<S> {
+    method: Method,
+    name: Option<String>,
+    template: String,
+    segments: Vec<Segment>,
+    handler: Handler<S>,
+    middleware: Vec<Middleware<S>>,
+    security_headers: Option<Arc<SecurityHeaders>>,
+}
+
+enum Segment {
+    Literal(String),
+    Param(String),
+}
+
+impl<S: Send + Sync + 'static> Route<S> {
+    /// `template` is a path like `/login/{provider}/start`, relative to the stage
+    pub fn new<F, Fut>(method: Method, template: &str, handler: F) -> Self
+    where
+        F: Fn(RouteRequest<S>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        let segments = split_path(template)
+            .map(|segment| {
+                match segment
+                    .strip_prefix('{')
+                    .and_then(|s| s.strip_suffix('}'))
+                {
+                    Some(name) => Segment::Param(name.to_owned()),
+                    None => Segment::Literal(segment.to_owned()),
+                }
+            })
+            .collect();
+        Self {
+            method,
+            name: None,
+            template: template.to_owned(),
+            segments,
+            handler: Arc::new(move |req| Box::pin(handler(req))),
+            middleware: vec![],
+            security_headers: None,
+        }
+    }
+
+    pub fn get<F, Fut>(template: &str, handler: F) -> Self
+    where
+        F: Fn(RouteRequest<S>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        Self::new(Method::GET, template, handler)
+    }
+
+    pub fn post<F, Fut>(template: &str, handler: F) -> Self
+    where
+        F: Fn(RouteRequest<S>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        Self::new(Method::POST, template, handler)
+    }
+
+    pub fn delete<F, Fut>(template: &str, handler: F) -> Self
+    where
+        F: Fn(RouteRequest<S>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = HandlerResult> + Send + 'static,
+    {
+        Self::new(Method::DELETE, template, handler)
+    }
+
+    /// Name the route so handlers can link to it with [`RouteRequest::url_for`]
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_owned());
+        self
+    }
+
+    /// Override the default security headers on this route's responses, errors included
+    pub fn security_headers(mut self, policy: SecurityHeaders) -> Self {
+        self.security_headers = Some(Arc::new(policy));
+        self
+    }
+
+    /// Middleware for this route only, runs after the router wide middleware
+    pub fn middleware(mut self, middleware: Middleware<S>) -> Self {
+        self.middleware.push(middleware);
+        self
+    }
+
+    fn matches(&self, path: &str) -> Option<HashMap<String, String>> {
+        let mut params = HashMap::new();
+        let mut parts = split_path(path);
+        for segment in &self.segments {
+            let part = parts.next()?;
+            match segment {
+                Segment::Literal(literal) if literal == part => {}
+                Segment::Literal(_) => return None,
+                Segment::Param(name) => {
+                    let value = percent_decode_str(part).decode_utf8().ok()?;
+                    params.insert(name.clone(), value.into_owned());
+                }
+            }
+        }
+        match parts.next() {
+            Some(_) => None,
+            None => Some(params),
+        }
+    }
+}
+
+/// Dispatches API Gateway v2 requests on method and path template
+pub struct Router<S> {
+    state: Arc<S>,
+    routes: Vec<Route<S>>,
+    middleware: Vec<Middleware<S>>,
+    cors: Option<CorsConfig>,
+    links: Arc<Links>,
+}
+
+impl<S: Send + Sync + 'static> Router<S> {
+    pub fn new(state: S) -> Self {
+        Self {
+            state: Arc::new(state),
+            routes: vec![],
+            middleware: vec![],
+            cors: None,
+            links: Arc::new(Links {
+                base_url: BaseUrl::RequestContext,
+                named: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Where absolute links built by handlers point, defaults to the request context
+    pub fn base_url(mut self, base_url: BaseUrl) -> Self {
+        Arc::make_mut(&mut self.links).base_url = base_url;
+        self
+    }
+
+    /// Answer preflight requests and add `Access-Control-*` headers to every response
+    pub fn cors(mut self, cors: Option<CorsConfig>) -> Self {
+        self.cors = cors;
+        self
+    }
+
+    pub fn route(mut self, route: Route<S>) -> Self {
+        if let Some(name) = &route.name {
+            Arc::make_mut(&mut self.links)
+                .named
+                .insert(name.clone(), route.template.clone());
+        }
+        self.routes.push(route);
+        self
+    }
+
+    /// Middleware for every route
+    pub fn layer(mut self, middleware: Middleware<S>) -> Self {
+        self.middleware.push(middleware);
+        self
+    }
+
+    /// Route the event, errors are rendered according to the `Accept` header
+    pub async fn handle(&self, event: LambdaEvent<Request>) -> Response {
+        let accept = event
+            .payload
+            .headers
+            .get("accept")
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+        let origin = event
+            .payload
+            .headers
+            .get("origin")
+            .and_then(|value| value.to_str().ok())
+            .map(String::from);
+
+        if let Some(preflight) = self
+            .cors
+            .as_ref()
+            .and_then(|cors| cors.preflight(&event.payload))
+        {
+            return preflight;
+        }
+
+        let (security_headers, res) = self.dispatch(event).await;
+        let mut res = match res {
+            Ok(res) => res,
+            Err(err) => {
+                let mut res = error_response(&err, accept.as_deref());
+                if let Error::MethodNotAllowed(allowed) = &err {
+                    let allowed = allowed
+                        .iter()
+                        .map(Method::as_str)
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    if let Ok(allowed) = HeaderValue::from_str(&allowed) {
+                        res.headers.insert(ALLOW, allowed);
+                    }
+                }
+                res
+            }
+        };
+        if let Some(policy) = security_headers {
+            policy.enforce(&mut res.headers);
+        }
+        if let Some(cors) = &self.cors {
+            cors.apply(origin.as_deref(), &mut res);
+        }
+        res
+    }
+
+    /// The matched route's security headers override, and what it answered
+    async fn dispatch(
+        &self,
+        event: LambdaEvent<Request>,
+    ) -> (Option<Arc<SecurityHeaders>>, HandlerResult) {
+        let LambdaEvent {
+            payload: request,
+            context,
+        } = event;
+        let path = stage_relative_path(&request);
+        let method = &request.request_context.http.method;
+
+        let mut allowed = vec![];
+        let mut found = None;
+        for route in &self.routes {
+            if let Some(params) = route.matches(&path) {
+                if route.method == *method {
+                    found = Some((route, params));
+                    break;
+                }
+                allowed.push(route.method.clone());
+            }
+        }
+
+        let Some((route, params)) = found else {
+            tracing::info!("no route for {} {}", method, path);
+            return match allowed.is_empty() {
+                true => (None, Err(Error::NotFound)),
+                false => (None, Err(Error::MethodNotAllowed(allowed))),
+            };
+        };
+
+        let req = RouteRequest {
+            request,
+            context,
+            state: self.state.clone(),
+            params,
+            links: self.links.clone(),
+        };
+        let next = Next {
+            middleware: self
+                .middleware
+                .iter()
+                .chain(&route.middleware)
+                .cloned()
+                .collect(),
+            handler: route.handler.clone(),
+        };
+        (route.security_headers.clone(), next.run(req).await)
+    }
+}
+
+/// The request path without the stage prefix API Gateway adds for named stages
+fn stage_relative_path(request: &Request) -> String {
+    let path = request
+        .raw_path
+        .as_deref()
+        .or(request.request_context.http.path.as_deref())
+        .unwrap_or("/");
+    let stage = request.request_context.stage.as_deref().unwrap_or("$default");
+    match path.strip_prefix(&format!("/{stage}")) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => format!("/{rest}"),
+        _ => path.to_owned(),
+    }
+}
+
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::ResponseBuilder;
+    use serde::Deserialize;
+
+    fn event(method: Method, raw_path: &str, query: &str) -> LambdaEvent<Request> {
+        let mut request = Request {
+            raw_path: Some(raw_path.to_owned()),
+            raw_query_string: Some(query.to_owned()),
+            ..Default::default()
+        };
+        request.request_context.stage = Some(String::from("Prod"));
+        request.request_context.http.method = method;
+        LambdaEvent::new(request, Context::default())
+    }
+
+    #[derive(Deserialize)]
+    struct Callback {
+        code: String,
+    }
+
+    fn router() -> Router<&'static str> {
+        Router::new("state")
+            .route(Route::get("/login/{provider}/start", |req| async move {
+                let provider: String = req.param("provider")?;
+                ResponseBuilder::ok()
+                    .text(format!("{} {provider}", req.state))
+                    .build()
+            }))
+            .route(Route::get("/login/{provider}/callback", |req| async move {
+                let query: Callback = req.query()?;
+                ResponseBuilder::ok().text(query.code).build()
+            }))
+            .route(
+                Route::delete("/login/{provider}/start", |_| async {
+                    ResponseBuilder::new(204).build()
+                })
+                .middleware(middleware(|req, next: Next<&'static str>| async move {
+                    let mut res = next.run(req).await?;
+                    res.headers.insert("x-route", HeaderValue::from_static("delete"));
+                    Ok(res)
+                }))
+                .security_headers(SecurityHeaders {
+                    frame_options: Some(String::from("SAMEORIGIN")),
+                    ..SecurityHeaders::default()
+                }),
+            )
+            .route(
+                Route::delete("/login/{provider}/callback", |_| async {
+                    Err(Error::Forbidden(String::from("no")))
+                })
+                .security_headers(SecurityHeaders {
+                    frame_options: Some(String::from("SAMEORIGIN")),
+                    ..SecurityHeaders::default()
+                }),
+            )
+    }
+
+    #[tokio::test]
+    async fn dispatches_with_params() {
+        let res = router()
+            .handle(event(Method::GET, "/Prod/login/github/start", ""))
+            .await;
+        assert_eq!(res.status_code, 200);
+        assert_eq!(
+            res.body,
+            Some(aws_lambda_events::encodings::Body::Text(String::from(
+                "state github"
+            )))
+        );
+
+        let res = router()
+            .handle(event(Method::GET, "/Prod/login/github/callback", "code=abc&state=x"))
+            .await;
+        assert_eq!(
+            res.body,
+            Some(aws_lambda_events::encodings::Body::Text(String::from("abc")))
+        );
+
+        let res = router()
+            .handle(event(Method::GET, "/Prod/login/github/callback", "state=x"))
+            .await;
+        assert_eq!(res.status_code, 400);
+    }
+
+    #[tokio::test]
+    async fn not_found_and_method_not_allowed() {
+        let res = router()
+            .handle(event(Method::GET, "/Prod/logout", ""))
+            .await;
+        assert_eq!(res.status_code, 404);
+
+        let res = router()
+            .handle(event(Method::POST, "/Prod/login/github/start", ""))
+            .await;
+        assert_eq!(res.status_code, 405);
+        assert_eq!(res.headers["Allow"], "GET, DELETE");
+    }
+
+    #[tokio::test]
+    async fn builds_links() {
+        let router = router()
+            .base_url(BaseUrl::Fixed(String::from("https://auth.example.com")))
+            .route(
+                Route::get("/login/{provider}/callback", |_| async { Err(Error::NotFound) })
+                    .name("callback"),
+            )
+            .route(Route::get("/links", |req| async move {
+                let url = req.url_for("callback", &[("provider", "git hub")])?;
+                ResponseBuilder::ok().text(url).build()
+            }));
+        let res = router.handle(event(Method::GET, "/Prod/links", "")).await;
+        assert_eq!(
+            res.body,
+            Some(aws_lambda_events::encodings::Body::Text(String::from(
+                "https://auth.example.com/login/git%20hub/callback"
+            )))
+        );
+    }
+
+    #[tokio::test]
+    async fn handles_cors() {
+        let router = router().cors(Some(CorsConfig {
+            allowed_origins: vec![String::from("https://app.example.com")],
+            ..CorsConfig::default()
+        }));
+
+        let mut preflight = event(Method::OPTIONS, "/Prod/login/github/start", "");
+        let headers = &mut preflight.payload.headers;
+        headers.insert("origin", HeaderValue::from_static("https://app.example.com"));
+        headers.insert("access-control-request-method", HeaderValue::from_static("GET"));
+        let res = router.handle(preflight).await;
+        assert_eq!(res.status_code, 204);
+        assert_eq!(res.headers["access-control-allow-origin"], "https://app.example.com");
+
+        let mut error = event(Method::GET, "/Prod/missing", "");
+        let headers = &mut error.payload.headers;
+        headers.insert("origin", HeaderValue::from_static("https://app.example.com"));
+        let res = router.handle(error).await;
+        assert_eq!(res.status_code, 404);
+        assert_eq!(res.headers["access-control-allow-origin"], "https://app.example.com");
+    }
+
+    #[tokio::test]
+    async fn runs_route_middleware() {
+        let res = router()
+            .handle(event(Method::DELETE, "/Prod/login/github/start", ""))
+            .await;
+        assert_eq!(res.status_code, 204);
+        assert_eq!(res.headers["x-route"], "delete");
+        assert_eq!(res.headers["x-frame-options"], "SAMEORIGIN");
+
+        let res = router()
+            .handle(event(Method::GET, "/Prod/login/github/start", ""))
+            .await;
+        assert!(!res.headers.contains_key("x-route"));
+        assert_eq!(res.headers["x-frame-options"], "DENY");
+
+        // errors of the route get its headers too
+        let res = router()
+            .handle(event(Method::DELETE, "/Prod/login/github/callback", ""))
+            .await;
+        assert_eq!(res.status_code, 403);
+        assert_eq!(res.headers["x-frame-options"], "SAMEORIGIN");
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+
+use crate::error::Error;
+
+/// Where secrets such as OAuth client credentials are read from
+#[derive(Debug, Clone)]
+pub enum SecretProvider {
+    /// SecureString parameters in SSM Parameter Store
+    Ssm(aws_sdk_ssm::Client),
+    /// Fixed values keyed by parameter name, for local development and tests
+    Static(HashMap<String, String>),
+}
+
+impl SecretProvider {
+    pub async fn ssm() -> Self {
+        SecretProvider::Ssm(crate::aws::ssm::create_client().await)
+    }
+
+    pub async fn get(&self, name: &str) -> Result<String, Error> {
+        let mut values = self.get_many(&[name]).await?;
+        Ok(values.remove(0))
+    }
+
+    /// Values in the order of `names`, fails if any of them is missing
+    pub async fn get_many(&self, names: &[&str]) -> Result<Vec<String>, Error> {
+        let mut found = match self {
+            SecretProvider::Ssm(client) => {
+                let res = client
+                    .get_parameters()
+                    .with_decryption(true)
+                    .set_names(Some(names.iter().map(|name| name.to_string()).collect()))
+                    .send()
+                    .await?;
+                res.parameters()
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|param| Some((param.name()?.to_owned(), param.value()?.to_owned())))
+                    .collect::<HashMap<_, _>>()
+            }
+            SecretProvider::Static(values) => values.clone(),
+        };
+
+        names
+            .iter()
+            .map(|name| {
+                found.remove(*name).ok_or(Error::Secret {
+                    name: name.to_string(),
+                    source: None,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn static_values() {
+        let secrets = SecretProvider::Static(HashMap::from([
+            (String::from("/a"), String::from("1")),
+            (String::from("/b"), String::from("2")),
+        ]));
+        assert_eq!(secrets.get_many(&["/b", "/a"]).await.unwrap(), vec!["2", "1"]);
+        let err = secrets.get("/c").await.unwrap_err();
+        assert_eq!(err.code(), "secret");
+    }
+}
@@ -0,0 +1,281 @@
+use async_session::{async_trait, Result, Session, SessionStore};
+use lru::LruCache;
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    time::{Duration, Instant},
+};
+
+use super::{StoredSession, UserSessions};
+
+const CAPACITY: usize = 1024;
+const TTL: Duration = Duration::from_secs(10);
+const NEGATIVE_TTL: Duration = Duration::from_secs(5);
+
+/// Hit and miss counts of a `CachedSessionStore` since it was built
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    /// Hits on a cached unknown session
+    pub negative_hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    hits: AtomicU64,
+    negative_hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// `None` for cookies the inner store did not know
+    stored: Option<StoredSession>,
+    cached_at: Instant,
+}
+
+/// Caches `load_session` of another store in memory, e.g. in `auth_fn` between warm
+/// invocations.
+///
+/// Entries live for a short TTL in a bounded LRU, unknown cookies for a shorter one.
+/// Storing or destroying a session through this store invalidates it here, but other
+/// instances keep serving their copy until it expires, so the TTL bounds how long a
+/// logout elsewhere takes to apply.
+#[derive(Debug, Clone)]
+pub struct CachedSessionStore<S> {
+    inner: S,
+    cache: Arc<Mutex<LruCache<String, Entry>>>,
+    ttl: Duration,
+    negative_ttl: Duration,
+    counters: Arc<Counters>,
+}
+
+impl<S: SessionStore> CachedSessionStore<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            cache: Arc::new(Mutex::new(LruCache::new(
+                NonZeroUsize::new(CAPACITY).expect("capacity is not zero"),
+            ))),
+            ttl: TTL,
+            negative_ttl: NEGATIVE_TTL,
+            counters: Arc::new(Counters::default()),
+        }
+    }
+
+    /// The defaults, with `SESSION_CACHE_SIZE`, `SESSION_CACHE_TTL_SECS` and
+    /// `SESSION_CACHE_NEGATIVE_TTL_SECS` replacing a setting
+    pub fn from_env(inner: S) -> Self {
+        let var = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse::<u64>().ok())
+        };
+        let mut store = Self::new(inner);
+        if let Some(capacity) = var("SESSION_CACHE_SIZE") {
+            store = store.with_capacity(capacity as usize);
+        }
+        if let Some(ttl) = var("SESSION_CACHE_TTL_SECS") {
+            store = store.with_ttl(Duration::from_secs(ttl));
+        }
+        if let Some(negative_ttl) = var("SESSION_CACHE_NEGATIVE_TTL_SECS") {
+            store = store.with_negative_ttl(Duration::from_secs(negative_ttl));
+        }
+        store
+    }
+
+    /// Most sessions kept, at least one
+    pub fn with_capacity(self, capacity: usize) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        self.lock().resize(capacity);
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// How long an unknown cookie is remembered, zero disables negative caching
+    pub fn with_negative_ttl(mut self, negative_ttl: Duration) -> Self {
+        self.negative_ttl = negative_ttl;
+        self
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            negative_hits: self.counters.negative_hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Drop every cached copy of session `id`
+    fn invalidate(&self, id: &str) {
+        let mut cache = self.lock();
+        let cookies = cache
+            .iter()
+            .filter(|(_, entry)| entry.stored.as_ref().is_some_and(|stored| stored.id == id))
+            .map(|(cookie, _)| cookie.clone())
+            .collect::<Vec<_>>();
+        for cookie in cookies {
+            cache.pop(&cookie);
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LruCache<String, Entry>> {
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[async_trait]
+impl<S: SessionStore> SessionStore for CachedSessionStore<S> {
+    async fn load_session(&self, cookie_value: String) -> Result<Option<Session>> {
+        let cached = {
+            let mut cache = self.lock();
+            match cache.get(&cookie_value) {
+                Some(entry) => {
+                    let ttl = match entry.stored {
+                        Some(_) => self.ttl,
+                        None => self.negative_ttl,
+                    };
+                    match entry.cached_at.elapsed() < ttl {
+                        true => Some(entry.stored.clone()),
+                        false => {
+                            cache.pop(&cookie_value);
+                            None
+                        }
+                    }
+                }
+                None => None,
+            }
+        };
+
+        match cached {
+            Some(Some(stored)) => {
+                self.counters.hits.fetch_add(1, Ordering::Relaxed);
+                // a fresh copy, callers must not share data with the cache
+                return stored.load();
+            }
+            Some(None) => {
+                self.counters.negative_hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(None);
+            }
+            None => {
+                self.counters.misses.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let session = self.inner.load_session(cookie_value.clone()).await?;
+        let stored = match &session {
+            Some(session) => Some(StoredSession::new(session)?),
+            None if self.negative_ttl.is_zero() => return Ok(None),
+            None => None,
+        };
+        self.lock().put(
+            cookie_value,
+            Entry {
+                stored,
+                cached_at: Instant::now(),
+            },
+        );
+        Ok(session)
+    }
+
+    async fn store_session(&self, session: Session) -> Result<Option<String>> {
+        self.invalidate(session.id());
+        self.inner.store_session(session).await
+    }
+
+    async fn destroy_session(&self, session: Session) -> Result {
+        self.invalidate(session.id());
+        self.inner.destroy_session(session).await
+    }
+
+    async fn clear_store(&self) -> Result {
+        self.lock().clear();
+        self.inner.clear_store().await
+    }
+}
+
+#[async_trait]
+impl<S: UserSessions> UserSessions for CachedSessionStore<S> {
+    async fn user_sessions(&self, user: &str) -> Result<Vec<String>> {
+        self.inner.user_sessions(user).await
+    }
+
+    async fn destroy_user_sessions(&self, user: &str) -> Result {
+        let ids = self.inner.user_sessions(user).await?;
+        for id in &ids {
+            self.invalidate(id);
+        }
+        self.inner.destroy_user_sessions(user).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::MemorySessionStore;
+
+    #[tokio::test]
+    async fn conformance() {
+        let store = CachedSessionStore::new(MemorySessionStore::new());
+        super::super::conformance::run(&store).await;
+    }
+
+    #[tokio::test]
+    async fn caches_loads() {
+        let store = CachedSessionStore::new(MemorySessionStore::new());
+        let mut session = Session::new();
+        session.insert("theme", "dark").unwrap();
+        let cookie = store.store_session(session).await.unwrap().unwrap();
+
+        let mut loaded = store.load_session(cookie.clone()).await.unwrap().unwrap();
+        loaded.insert("theme", "light").unwrap();
+        let cached = store.load_session(cookie.clone()).await.unwrap().unwrap();
+        assert_eq!(cached.get::<String>("theme").as_deref(), Some("dark"));
+
+        // served from the cache while the inner store no longer has it
+        store.inner().clear_store().await.unwrap();
+        assert!(store.load_session(cookie.clone()).await.unwrap().is_some());
+
+        store.destroy_session(cached).await.unwrap();
+        assert!(store.load_session(cookie).await.unwrap().is_none());
+
+        let unknown = Session::new().into_cookie_value().unwrap();
+        assert!(store.load_session(unknown.clone()).await.unwrap().is_none());
+        assert!(store.load_session(unknown).await.unwrap().is_none());
+
+        assert_eq!(
+            store.stats(),
+            CacheStats {
+                hits: 2,
+                negative_hits: 1,
+                misses: 3,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn expires_entries() {
+        let store = CachedSessionStore::new(MemorySessionStore::new())
+            .with_ttl(Duration::ZERO)
+            .with_capacity(1);
+        let cookie = store.store_session(Session::new()).await.unwrap().unwrap();
+        store.load_session(cookie.clone()).await.unwrap();
+        store.inner().clear_store().await.unwrap();
+        assert!(store.load_session(cookie).await.unwrap().is_none());
+        assert_eq!(store.stats().hits, 0);
+    }
+}
@@ -0,0 +1,285 @@
+use aes_gcm::{
+    aead::{Aead, OsRng, Payload},
+    AeadCore, Aes256Gcm,
+};
+use async_session::{async_trait, Result, Session, SessionStore};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use super::StoredSession;
+use crate::{aws::dynamodb::DbClient, envelope::AesKey, error::Error, secrets::SecretProvider};
+
+const VERSION: &str = "v1";
+const NONCE_LEN: usize = 12;
+/// Browsers drop cookies over 4096 bytes including the name and attributes
+const MAX_COOKIE_SIZE: usize = 3800;
+const REVOKED_PK: &str = "REVOKED_SESSION";
+
+/// Revocation marker for a sealed session, kept until the session would have expired
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct RevokedSession {
+    #[serde(rename = "PK")]
+    pk: String,
+    #[serde(rename = "SK")]
+    sk: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl: Option<i64>,
+}
+
+/// Stateless sessions sealed into the cookie itself with AES-256-GCM.
+///
+/// The cookie is `v1.<key id>.<base64url nonce and ciphertext>`, the prefix is bound as
+/// associated data. Cookies that fail to open are treated like unknown sessions. Without
+/// a revocation list `destroy_session` cannot end a session before it expires, with one
+/// every load costs a DynamoDB read again.
+#[derive(Debug, Clone)]
+pub struct CookieSessionStore {
+    keys: Arc<Vec<AesKey>>,
+    max_size: usize,
+    revocations: Option<Arc<DbClient>>,
+}
+
+impl CookieSessionStore {
+    /// Seals with the first key, opens with any of them
+    pub fn new(keys: Vec<AesKey>) -> std::result::Result<Self, Error> {
+        if keys.is_empty() {
+            return Err(Error::config("no cookie keys configured"));
+        }
+        Ok(Self {
+            keys: Arc::new(keys),
+            max_size: MAX_COOKIE_SIZE,
+            revocations: None,
+        })
+    }
+
+    /// The keys in the secret named by `PARAM_SESSION_COOKIE_KEYS`, see `AesKey::parse_list`
+    pub async fn from_secrets(secrets: &SecretProvider) -> std::result::Result<Self, Error> {
+        let Ok(param) = std::env::var("PARAM_SESSION_COOKIE_KEYS") else {
+            return Err(Error::config("PARAM_SESSION_COOKIE_KEYS not set"));
+        };
+        Self::new(AesKey::parse_list(&secrets.get(&param).await?)?)
+    }
+
+    /// Largest cookie value `store_session` will produce
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Record destroyed sessions in the table so logout ends them before they expire
+    pub fn with_revocations(mut self, db: Arc<DbClient>) -> Self {
+        self.revocations = Some(db);
+        self
+    }
+
+    fn seal(&self, plaintext: &[u8]) -> Result<String> {
+        let key = &self.keys[0];
+        let header = format!("{VERSION}.{}", key.id());
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = key
+            .cipher()
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: plaintext,
+                    aad: header.as_bytes(),
+                },
+            )
+            .map_err(|_| async_session::Error::msg("failed to seal session"))?;
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        Ok(format!("{header}.{}", URL_SAFE_NO_PAD.encode(sealed)))
+    }
+
+    fn open(&self, cookie_value: &str) -> Option<Vec<u8>> {
+        let mut parts = cookie_value.splitn(3, '.');
+        let (Some(VERSION), Some(key_id), Some(sealed)) = (parts.next(), parts.next(), parts.next())
+        else {
+            tracing::info!("malformed session cookie");
+            return None;
+        };
+        let Some(key) = self.keys.iter().find(|key| key.id() == key_id) else {
+            tracing::info!("session cookie sealed with unknown key `{}`", key_id);
+            return None;
+        };
+        let sealed = URL_SAFE_NO_PAD.decode(sealed).ok()?;
+        if sealed.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let header = format!("{VERSION}.{key_id}");
+        key.cipher()
+            .decrypt(
+                nonce.into(),
+                Payload {
+                    msg: ciphertext,
+                    aad: header.as_bytes(),
+                },
+            )
+            .map_err(|_| tracing::info!("session cookie failed to open"))
+            .ok()
+    }
+
+    async fn is_revoked(&self, id: &str) -> Result<bool> {
+        let Some(db) = &self.revocations else {
+            return Ok(false);
+        };
+        let revoked = db
+            .query_single_table::<RevokedSession>(
+                REVOKED_PK.to_string(),
+                Some(id.to_owned()),
+                None,
+            )
+            .await
+            .map_err(|err| {
+                tracing::error!("load revocation error {:?}", err);
+                async_session::Error::msg(format!("load revocation error {}", err))
+            })?;
+        Ok(!revoked.is_empty())
+    }
+}
+
+#[async_trait]
+impl SessionStore for CookieSessionStore {
+    async fn load_session(&self, cookie_value: String) -> Result<Option<Session>> {
+        let Some(json) = self.open(&cookie_value) else {
+            return Ok(None);
+        };
+        let Some(session) = serde_json::from_slice::<Session>(&json)?.validate() else {
+            return Ok(None);
+        };
+        tracing::info!("loaded sealed session `{}`", session.id());
+        if self.is_revoked(session.id()).await? {
+            tracing::info!("session `{}` was revoked", session.id());
+            return Ok(None);
+        }
+        Ok(Some(session))
+    }
+
+    async fn store_session(&self, session: Session) -> Result<Option<String>> {
+        tracing::info!("sealing session by id `{}`", session.id());
+        let stored = StoredSession::new(&session)?;
+        let cookie_value = self.seal(stored.json.as_bytes())?;
+        if cookie_value.len() > self.max_size {
+            return Err(async_session::Error::msg(format!(
+                "sealed session is {} bytes, over the {} byte limit",
+                cookie_value.len(),
+                self.max_size
+            )));
+        }
+        session.reset_data_changed();
+        Ok(Some(cookie_value))
+    }
+
+    async fn destroy_session(&self, session: Session) -> Result {
+        tracing::info!("destroying session by id `{}`", session.id());
+        let Some(db) = &self.revocations else {
+            tracing::warn!("no revocation list, session `{}` stays valid", session.id());
+            return Ok(());
+        };
+        let revoked = RevokedSession {
+            pk: String::from(REVOKED_PK),
+            sk: session.id().to_owned(),
+            ttl: session.expiry().map(|expiry| expiry.timestamp()),
+        };
+        if let Err(err) = db.put(&revoked).await {
+            tracing::error!("store revocation error {:?}", err);
+            return Err(async_session::Error::msg(format!(
+                "store revocation error {}",
+                err
+            )));
+        }
+        Ok(())
+    }
+
+    async fn clear_store(&self) -> Result {
+        Err(async_session::Error::msg(
+            "sealed cookie sessions cannot be cleared, rotate the cookie keys instead",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::User;
+    use async_session::chrono::{Duration, Utc};
+
+    fn key(id: &str, byte: u8) -> AesKey {
+        AesKey::new(id, &[byte; 32]).unwrap()
+    }
+
+    fn session() -> Session {
+        let mut session = Session::new();
+        session
+            .insert(
+                "user",
+                User {
+                    id: String::from("a"),
+                    email: String::from("a@example.com"),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        session.expire_in(std::time::Duration::from_secs(60));
+        session
+    }
+
+    #[tokio::test]
+    async fn seals_and_opens_sessions() {
+        let store = CookieSessionStore::new(vec![key("k1", 1)]).unwrap();
+        let session = session();
+        let id = session.id().to_owned();
+        let cookie = store.store_session(session).await.unwrap().unwrap();
+        assert!(cookie.starts_with("v1.k1."));
+        assert!(!cookie.contains("a@example.com"));
+
+        let loaded = store.load_session(cookie.clone()).await.unwrap().unwrap();
+        assert_eq!(loaded.id(), id);
+        assert_eq!(loaded.get::<User>("user").unwrap().email, "a@example.com");
+
+        let mut tampered = cookie.into_bytes();
+        let last = tampered.len() - 1;
+        tampered[last] = if tampered[last] == b'A' { b'B' } else { b'A' };
+        let tampered = String::from_utf8(tampered).unwrap();
+        assert!(store.load_session(tampered).await.unwrap().is_none());
+        assert!(store.load_session(String::from("garbage")).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn rotates_keys() {
+        let old = CookieSessionStore::new(vec![key("k1", 1)]).unwrap();
+        let cookie = old.store_session(session()).await.unwrap().unwrap();
+
+        let rotated = CookieSessionStore::new(vec![key("k2", 2), key("k1", 1)]).unwrap();
+        assert!(rotated.load_session(cookie.clone()).await.unwrap().is_some());
+        let resealed = rotated.store_session(session()).await.unwrap().unwrap();
+        assert!(resealed.starts_with("v1.k2."));
+
+        let retired = CookieSessionStore::new(vec![key("k2", 2)]).unwrap();
+        assert!(retired.load_session(cookie).await.unwrap().is_none());
+
+        // the key id is authenticated, relabelling a cookie does not open it
+        let same_key = CookieSessionStore::new(vec![key("k3", 2), key("k2", 2)]).unwrap();
+        let relabelled = resealed.replacen("v1.k2.", "v1.k3.", 1);
+        assert!(same_key.load_session(relabelled).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn enforces_size_and_expiry() {
+        let store = CookieSessionStore::new(vec![key("k1", 1)])
+            .unwrap()
+            .with_max_size(200);
+        let mut large = session();
+        large.insert("blob", "x".repeat(500)).unwrap();
+        assert!(store.store_session(large).await.is_err());
+
+        let store = CookieSessionStore::new(vec![key("k1", 1)]).unwrap();
+        let mut expired = session();
+        expired.set_expiry(Utc::now() - Duration::seconds(5));
+        let cookie = store.store_session(expired).await.unwrap().unwrap();
+        assert!(store.load_session(cookie).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,393 @@
+use async_session::{async_trait, Result, Session, SessionStore};
+use aws_sdk_dynamodb::types::AttributeValue;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, sync::Arc};
+
+use super::{session_user, StoredSession, UserSessions};
+use crate::{
+    aws::dynamodb::DbClient,
+    envelope::{Envelope, Sealed},
+};
+
+const SESSION_PK: &str = "SESSION";
+const USER_SESSION_SK_PREFIX: &str = "SESSION#";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DynamoSession {
+    #[serde(rename = "PK")]
+    pk: String,
+    #[serde(rename = "SK")]
+    sk: String,
+    /// The session JSON, empty when it is `sealed` instead
+    #[serde(default)]
+    session: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sealed: Option<Sealed>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    /// Unix seconds, the table's TTL attribute
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl: Option<i64>,
+}
+
+impl DynamoSession {
+    pub fn new(id: &str, session: String) -> Self {
+        Self {
+            pk: String::from(SESSION_PK),
+            sk: id.to_string(),
+            session,
+            sealed: None,
+            user: None,
+            ttl: None,
+        }
+    }
+}
+
+impl DynamoSession {
+    /// The item for `stored`, with the session JSON sealed when `envelope` is set
+    async fn seal(stored: StoredSession, envelope: Option<&Envelope>) -> Result<Self> {
+        let Some(envelope) = envelope else {
+            return Ok(Self::from(stored));
+        };
+        let sealed = envelope
+            .seal(stored.json.as_bytes(), stored.id.as_bytes())
+            .await?;
+        Ok(Self {
+            sealed: Some(sealed),
+            ..Self::from(StoredSession {
+                json: String::new(),
+                ..stored
+            })
+        })
+    }
+
+    /// Plaintext items are read as they are, sealed ones need the envelope
+    async fn open(self, envelope: Option<&Envelope>) -> Result<StoredSession> {
+        let json = match (&self.sealed, envelope) {
+            (None, _) => self.session,
+            (Some(sealed), Some(envelope)) => {
+                String::from_utf8(envelope.open(sealed, self.sk.as_bytes()).await?)?
+            }
+            (Some(_), None) => {
+                return Err(async_session::Error::msg(
+                    "session is encrypted but no key provider is configured",
+                ))
+            }
+        };
+        Ok(StoredSession {
+            id: self.sk,
+            user: self.user,
+            expires_at: self.ttl,
+            json,
+        })
+    }
+}
+
+impl From<StoredSession> for DynamoSession {
+    fn from(stored: StoredSession) -> Self {
+        Self {
+            user: stored.user,
+            ttl: stored.expires_at,
+            ..Self::new(&stored.id, stored.json)
+        }
+    }
+}
+
+/// Index item in the user's partition, `PK=u#<user>` and `SK=SESSION#<id>`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DynamoUserSession {
+    #[serde(rename = "PK")]
+    pk: String,
+    #[serde(rename = "SK")]
+    sk: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl: Option<i64>,
+}
+
+impl DynamoUserSession {
+    pub fn new(user: &str, id: &str, ttl: Option<i64>) -> Self {
+        Self {
+            pk: user_pk(user),
+            sk: format!("{USER_SESSION_SK_PREFIX}{id}"),
+            ttl,
+        }
+    }
+
+    fn session_id(&self) -> &str {
+        self.sk.trim_start_matches(USER_SESSION_SK_PREFIX)
+    }
+
+    fn is_expired(&self) -> bool {
+        self.ttl.is_some_and(|ttl| ttl <= super::now())
+    }
+}
+
+fn user_pk(user: &str) -> String {
+    format!("u#{user}")
+}
+
+#[derive(Debug, Clone)]
+pub struct DynamoSessionStore {
+    db: Arc<DbClient>,
+    envelope: Option<Envelope>,
+}
+
+impl DynamoSessionStore {
+    pub async fn new(db: Arc<DbClient>) -> DynamoSessionStore {
+        Self { db, envelope: None }
+    }
+
+    /// Seal the session JSON of new items, the `user` and `ttl` attributes stay readable for
+    /// the per-user index and TTL. Plaintext items written before are still loaded.
+    pub fn with_encryption(mut self, envelope: Envelope) -> Self {
+        self.envelope = Some(envelope);
+        self
+    }
+}
+
+#[async_trait]
+impl SessionStore for DynamoSessionStore {
+    // query -> pk SESSION sk 1234
+    async fn load_session(&self, cookie_value: String) -> Result<Option<Session>> {
+        let id = Session::id_from_cookie_value(&cookie_value)?;
+        tracing::info!("loading session by id `{}`", id);
+
+        match self
+            .db
+            .query_single_table::<DynamoSession>(SESSION_PK.to_string(), Some(id), None)
+            .await
+        {
+            Err(err) => {
+                tracing::error!("load session error {}", err);
+                Err(async_session::Error::msg(format!(
+                    "load session error {}",
+                    err
+                )))
+            }
+            Ok(res) => match res.into_iter().next() {
+                None => {
+                    tracing::info!("load session not found");
+                    Ok(None)
+                }
+                Some(dbs) => dbs
+                    .open(self.envelope.as_ref())
+                    .await
+                    .and_then(|stored| stored.load())
+                    .map_err(|err| {
+                        tracing::error!("load session error {}", err);
+                        async_session::Error::msg(format!("load session error {}", err))
+                    }),
+            },
+        }
+    }
+
+    async fn store_session(&self, session: Session) -> Result<Option<String>> {
+        tracing::info!("storing session by id `{}`", session.id());
+        let stored = StoredSession::new(&session)?;
+        let index = stored
+            .user
+            .as_deref()
+            .map(|user| DynamoUserSession::new(user, &stored.id, stored.expires_at));
+        let db_session = DynamoSession::seal(stored, self.envelope.as_ref()).await?;
+
+        if let Some(index) = index {
+            if let Err(err) = self.db.put(&index).await {
+                tracing::error!("store user session index error {:?}", err);
+                return Err(async_session::Error::msg(format!(
+                    "store session error {}",
+                    err
+                )));
+            }
+        }
+        match self.db.put(&db_session).await {
+            Err(err) => {
+                tracing::error!("store session error {:?}", err);
+                Err(async_session::Error::msg(format!(
+                    "store session error {}",
+                    err
+                )))
+            }
+            Ok(res) => {
+                tracing::info!("store session success {:?}", res);
+                session.reset_data_changed();
+                Ok(session.into_cookie_value())
+            }
+        }
+    }
+
+    async fn destroy_session(&self, session: Session) -> Result {
+        tracing::info!("destroying session by id `{}`", session.id());
+        if let Some(user) = session_user(&session) {
+            let index = DynamoUserSession::new(&user, session.id(), None);
+            if let Err(err) = self.db.delete(index.pk, index.sk).await {
+                tracing::error!("failed to delete user session index {:?}", err);
+            }
+        }
+        match self
+            .db
+            .delete(String::from(SESSION_PK), session.id().to_string())
+            .await
+        {
+            Err(err) => {
+                tracing::error!("store session error {:?}", err);
+                Err(async_session::Error::msg(format!(
+                    "store session error {}",
+                    err
+                )))
+            }
+            Ok(res) => {
+                tracing::info!("delete session success {:?}", res);
+                Ok(())
+            }
+        }
+    }
+
+    async fn clear_store(&self) -> Result {
+        tracing::info!("clearing session store");
+
+        match self.db
+            .query::<DynamoSession>(
+                "#pk = :pk",
+                HashMap::from([(String::from("#pk"), String::from("PK"))]),
+                HashMap::from([(
+                    String::from(":pk"),
+                    AttributeValue::S(SESSION_PK.to_string()),
+                )]),
+                None,
+            )
+            .await
+        {
+            Err(err) => {
+                tracing::error!("load session error {:?}", err);
+                Err(async_session::Error::msg(format!(
+                    "load session error {}",
+                    err
+                )))
+            }
+            Ok(db_sessions) => {
+                for item in db_sessions {
+                    //TODO join futures
+                    if let Some(user) = &item.user {
+                        let index = DynamoUserSession::new(user, &item.sk, None);
+                        if let Err(err) = self.db.delete(index.pk, index.sk).await {
+                            tracing::error!("failed to delete user session index {:?}", err);
+                        }
+                    }
+                    if let Err(err) = self.db.delete(item.pk, item.sk).await {
+                        tracing::error!("failed to delete session {:?}", err);
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl DynamoSessionStore {
+    async fn user_index(&self, user: &str) -> Result<Vec<DynamoUserSession>> {
+        self.db
+            .query::<DynamoUserSession>(
+                "#pk = :pk and begins_with(#sk, :sk)",
+                HashMap::from([
+                    (String::from("#pk"), String::from("PK")),
+                    (String::from("#sk"), String::from("SK")),
+                ]),
+                HashMap::from([
+                    (String::from(":pk"), AttributeValue::S(user_pk(user))),
+                    (
+                        String::from(":sk"),
+                        AttributeValue::S(USER_SESSION_SK_PREFIX.to_string()),
+                    ),
+                ]),
+                None,
+            )
+            .await
+            .map_err(|err| {
+                tracing::error!("load user sessions error {:?}", err);
+                async_session::Error::msg(format!("load user sessions error {}", err))
+            })
+    }
+}
+
+#[async_trait]
+impl UserSessions for DynamoSessionStore {
+    async fn user_sessions(&self, user: &str) -> Result<Vec<String>> {
+        Ok(self
+            .user_index(user)
+            .await?
+            .iter()
+            .filter(|index| !index.is_expired())
+            .map(|index| index.session_id().to_owned())
+            .collect())
+    }
+
+    async fn destroy_user_sessions(&self, user: &str) -> Result {
+        tracing::info!("destroying all sessions of `{}`", user);
+        for index in self.user_index(user).await? {
+            //TODO join futures
+            if let Err(err) = self
+                .db
+                .delete(String::from(SESSION_PK), index.session_id().to_owned())
+                .await
+            {
+                tracing::error!("failed to delete session {:?}", err);
+            }
+            if let Err(err) = self.db.delete(index.pk, index.sk).await {
+                tracing::error!("failed to delete user session index {:?}", err);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "requires DynamoDB Local at DYNAMODB_ENDPOINT and TABLE_NAME"]
+    async fn conformance() {
+        let table_name = std::env::var("TABLE_NAME").unwrap();
+        let store = DynamoSessionStore::new(DbClient::new(&table_name).await).await;
+        super::super::conformance::run(&store).await;
+    }
+
+    #[tokio::test]
+    async fn seals_session_payloads() {
+        use crate::{
+            envelope::{AesKey, KeyProvider},
+            model::User,
+        };
+        use serde_dynamo::aws_sdk_dynamodb_0_28::{from_item, to_item};
+
+        let mut session = Session::new();
+        session
+            .insert(
+                "user",
+                User {
+                    id: String::from("a"),
+                    email: String::from("a@example.com"),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let stored = StoredSession::new(&session).unwrap();
+        let envelope = Envelope::new(KeyProvider::Local(vec![
+            AesKey::new("k1", &[1; 32]).unwrap()
+        ]));
+
+        let item = DynamoSession::seal(stored.clone(), Some(&envelope)).await.unwrap();
+        let item = to_item(item).unwrap();
+        assert_eq!(item["session"], AttributeValue::S(String::new()));
+        assert!(!format!("{:?}", item["sealed"]).contains("example.com"));
+
+        let item = from_item::<DynamoSession>(item).unwrap();
+        assert!(item.clone().open(None).await.is_err());
+        let opened = item.open(Some(&envelope)).await.unwrap();
+        assert_eq!(opened.json, stored.json);
+
+        let plaintext = DynamoSession::seal(stored.clone(), None).await.unwrap();
+        let opened = plaintext.open(Some(&envelope)).await.unwrap();
+        assert_eq!(opened.json, stored.json);
+    }
+}
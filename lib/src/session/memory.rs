@@ -0,0 +1,198 @@
+use async_session::{async_trait, Result, Session, SessionStore};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use super::{StoredSession, UserSessions};
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Sessions in process memory, for local development and tests.
+///
+/// Expired sessions are never returned and are swept out on writes at most once per
+/// sweep interval, or on demand with `sweep`.
+#[derive(Debug, Clone)]
+pub struct MemorySessionStore {
+    inner: Arc<RwLock<Inner>>,
+    sweep_interval: Duration,
+}
+
+#[derive(Debug)]
+struct Inner {
+    sessions: HashMap<String, StoredSession>,
+    users: HashMap<String, HashSet<String>>,
+    last_sweep: Instant,
+}
+
+impl Default for MemorySessionStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MemorySessionStore {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(RwLock::new(Inner {
+                sessions: HashMap::new(),
+                users: HashMap::new(),
+                last_sweep: Instant::now(),
+            })),
+            sweep_interval: SWEEP_INTERVAL,
+        }
+    }
+
+    pub fn with_sweep_interval(mut self, sweep_interval: Duration) -> Self {
+        self.sweep_interval = sweep_interval;
+        self
+    }
+
+    /// Drop expired sessions, returns how many were removed
+    pub fn sweep(&self) -> usize {
+        let mut inner = self.write();
+        inner.sweep()
+    }
+
+    /// Number of stored sessions, including expired ones not yet swept
+    pub fn count(&self) -> usize {
+        self.read().sessions.len()
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Inner> {
+        self.inner.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Inner> {
+        self.inner.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Inner {
+    fn sweep(&mut self) -> usize {
+        self.last_sweep = Instant::now();
+        let expired = self
+            .sessions
+            .values()
+            .filter(|stored| stored.is_expired())
+            .map(|stored| stored.id.clone())
+            .collect::<Vec<_>>();
+        for id in &expired {
+            self.remove(id);
+        }
+        if !expired.is_empty() {
+            tracing::info!("swept {} expired sessions", expired.len());
+        }
+        expired.len()
+    }
+
+    fn remove(&mut self, id: &str) -> Option<StoredSession> {
+        let stored = self.sessions.remove(id)?;
+        if let Some(user) = &stored.user {
+            if let Some(ids) = self.users.get_mut(user) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    self.users.remove(user);
+                }
+            }
+        }
+        Some(stored)
+    }
+}
+
+#[async_trait]
+impl SessionStore for MemorySessionStore {
+    async fn load_session(&self, cookie_value: String) -> Result<Option<Session>> {
+        let id = Session::id_from_cookie_value(&cookie_value)?;
+        tracing::info!("loading session by id `{}`", id);
+        match self.read().sessions.get(&id) {
+            Some(stored) => stored.load(),
+            None => Ok(None),
+        }
+    }
+
+    async fn store_session(&self, session: Session) -> Result<Option<String>> {
+        tracing::info!("storing session by id `{}`", session.id());
+        let stored = StoredSession::new(&session)?;
+
+        let mut inner = self.write();
+        if inner.last_sweep.elapsed() >= self.sweep_interval {
+            inner.sweep();
+        }
+        inner.remove(&stored.id);
+        if let Some(user) = &stored.user {
+            inner
+                .users
+                .entry(user.clone())
+                .or_default()
+                .insert(stored.id.clone());
+        }
+        inner.sessions.insert(stored.id.clone(), stored);
+
+        session.reset_data_changed();
+        Ok(session.into_cookie_value())
+    }
+
+    async fn destroy_session(&self, session: Session) -> Result {
+        tracing::info!("destroying session by id `{}`", session.id());
+        self.write().remove(session.id());
+        Ok(())
+    }
+
+    async fn clear_store(&self) -> Result {
+        tracing::info!("clearing session store");
+        let mut inner = self.write();
+        inner.sessions.clear();
+        inner.users.clear();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UserSessions for MemorySessionStore {
+    async fn user_sessions(&self, user: &str) -> Result<Vec<String>> {
+        let inner = self.read();
+        Ok(inner
+            .users
+            .get(user)
+            .into_iter()
+            .flatten()
+            .filter(|id| inner.sessions.get(*id).is_some_and(|stored| !stored.is_expired()))
+            .cloned()
+            .collect())
+    }
+
+    async fn destroy_user_sessions(&self, user: &str) -> Result {
+        tracing::info!("destroying all sessions of `{}`", user);
+        let mut inner = self.write();
+        for id in inner.users.remove(user).unwrap_or_default() {
+            inner.remove(&id);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_session::chrono::{Duration as ChronoDuration, Utc};
+
+    #[tokio::test]
+    async fn conformance() {
+        super::super::conformance::run(&MemorySessionStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn sweeps_expired_sessions() {
+        let store = MemorySessionStore::new().with_sweep_interval(Duration::ZERO);
+        let mut expired = Session::new();
+        expired.set_expiry(Utc::now() - ChronoDuration::seconds(5));
+        store.store_session(expired).await.unwrap();
+        assert_eq!(store.count(), 1);
+
+        store.store_session(Session::new()).await.unwrap();
+        assert_eq!(store.count(), 1);
+        assert_eq!(store.sweep(), 0);
+    }
+}
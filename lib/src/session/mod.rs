@@ -0,0 +1,256 @@
+//! `SessionStore` implementations that share one storage format: the `Session` as JSON,
+//! its expiry as unix seconds and the user it belongs to, so a user's sessions can be
+//! listed and revoked together.
+
+mod cache;
+mod cookie;
+mod dynamo;
+mod memory;
+mod redis;
+mod sqlite;
+
+pub use self::redis::RedisSessionStore;
+pub use cache::{CacheStats, CachedSessionStore};
+pub use cookie::CookieSessionStore;
+pub use dynamo::{DynamoSession, DynamoSessionStore, DynamoUserSession};
+pub use memory::MemorySessionStore;
+pub use sqlite::SqliteSessionStore;
+
+use async_session::{async_trait, chrono::Utc, Result, Session, SessionStore};
+
+use crate::{
+    aws::dynamodb::DbClient,
+    envelope::{Envelope, KeyProvider},
+    error::Error,
+    model::User,
+    secrets::SecretProvider,
+};
+
+/// Stores that index sessions by the user they belong to
+#[async_trait]
+pub trait UserSessions: SessionStore {
+    /// Ids of the user's sessions that have not expired
+    async fn user_sessions(&self, user: &str) -> Result<Vec<String>>;
+
+    /// Destroy all of the user's sessions, e.g. to sign out everywhere
+    async fn destroy_user_sessions(&self, user: &str) -> Result;
+}
+
+/// The production store picked by configuration, so functions can switch backends
+/// without being generic over them
+#[derive(Debug, Clone)]
+pub enum ConfiguredSessionStore {
+    Dynamo(DynamoSessionStore),
+    Redis(Box<RedisSessionStore>),
+    Cookie(CookieSessionStore),
+}
+
+impl ConfiguredSessionStore {
+    /// `SESSION_STORE=redis` connects to `REDIS_URL`, `SESSION_STORE=cookie` seals sessions
+    /// into the cookie with the keys named by `PARAM_SESSION_COOKIE_KEYS`, revoking them in
+    /// `TABLE_NAME` when it is set. Otherwise sessions go to the DynamoDB table `TABLE_NAME`,
+    /// encrypted as `KeyProvider::from_env` configures.
+    pub async fn from_env(secrets: &SecretProvider) -> std::result::Result<Self, Error> {
+        match std::env::var("SESSION_STORE").as_deref() {
+            Ok("cookie") => {
+                let mut store = CookieSessionStore::from_secrets(secrets).await?;
+                if let Ok(table_name) = std::env::var("TABLE_NAME") {
+                    store = store.with_revocations(DbClient::new(&table_name).await);
+                }
+                Ok(ConfiguredSessionStore::Cookie(store))
+            }
+            Ok("redis") => {
+                let Ok(url) = std::env::var("REDIS_URL") else {
+                    return Err(Error::config("ENV VAR REDIS_URL not set"));
+                };
+                let store = RedisSessionStore::new(&url).await.map_err(|err| {
+                    Error::storage("failed to connect to redis").with_source(err)
+                })?;
+                Ok(ConfiguredSessionStore::Redis(Box::new(store)))
+            }
+            Ok("dynamo") | Err(_) => {
+                let Ok(table_name) = std::env::var("TABLE_NAME") else {
+                    return Err(Error::config("ENV VAR TABLE_NAME not set"));
+                };
+                let db_client = DbClient::new(&table_name).await;
+                let mut store = DynamoSessionStore::new(db_client).await;
+                if let Some(provider) = KeyProvider::from_env().await? {
+                    store = store.with_encryption(Envelope::new(provider));
+                }
+                Ok(ConfiguredSessionStore::Dynamo(store))
+            }
+            Ok(other) => Err(Error::config(format!("unknown SESSION_STORE `{other}`"))),
+        }
+    }
+}
+
+#[async_trait]
+impl SessionStore for ConfiguredSessionStore {
+    async fn load_session(&self, cookie_value: String) -> Result<Option<Session>> {
+        match self {
+            ConfiguredSessionStore::Dynamo(store) => store.load_session(cookie_value).await,
+            ConfiguredSessionStore::Redis(store) => store.load_session(cookie_value).await,
+            ConfiguredSessionStore::Cookie(store) => store.load_session(cookie_value).await,
+        }
+    }
+
+    async fn store_session(&self, session: Session) -> Result<Option<String>> {
+        match self {
+            ConfiguredSessionStore::Dynamo(store) => store.store_session(session).await,
+            ConfiguredSessionStore::Redis(store) => store.store_session(session).await,
+            ConfiguredSessionStore::Cookie(store) => store.store_session(session).await,
+        }
+    }
+
+    async fn destroy_session(&self, session: Session) -> Result {
+        match self {
+            ConfiguredSessionStore::Dynamo(store) => store.destroy_session(session).await,
+            ConfiguredSessionStore::Redis(store) => store.destroy_session(session).await,
+            ConfiguredSessionStore::Cookie(store) => store.destroy_session(session).await,
+        }
+    }
+
+    async fn clear_store(&self) -> Result {
+        match self {
+            ConfiguredSessionStore::Dynamo(store) => store.clear_store().await,
+            ConfiguredSessionStore::Redis(store) => store.clear_store().await,
+            ConfiguredSessionStore::Cookie(store) => store.clear_store().await,
+        }
+    }
+}
+
+#[async_trait]
+impl UserSessions for ConfiguredSessionStore {
+    async fn user_sessions(&self, user: &str) -> Result<Vec<String>> {
+        match self {
+            ConfiguredSessionStore::Dynamo(store) => store.user_sessions(user).await,
+            ConfiguredSessionStore::Redis(store) => store.user_sessions(user).await,
+            ConfiguredSessionStore::Cookie(_) => Err(not_indexed()),
+        }
+    }
+
+    async fn destroy_user_sessions(&self, user: &str) -> Result {
+        match self {
+            ConfiguredSessionStore::Dynamo(store) => store.destroy_user_sessions(user).await,
+            ConfiguredSessionStore::Redis(store) => store.destroy_user_sessions(user).await,
+            ConfiguredSessionStore::Cookie(_) => Err(not_indexed()),
+        }
+    }
+}
+
+fn not_indexed() -> async_session::Error {
+    async_session::Error::msg("sealed cookie sessions are not indexed by user")
+}
+
+/// The id of the user a session is indexed under, if it is signed in
+pub fn session_user(session: &Session) -> Option<String> {
+    session
+        .get::<User>("user")
+        .map(|user| user.id)
+        .filter(|id| !id.is_empty())
+}
+
+/// A session as every store persists it
+#[derive(Debug, Clone)]
+pub(crate) struct StoredSession {
+    pub id: String,
+    pub user: Option<String>,
+    /// Unix seconds, also used as the DynamoDB TTL attribute
+    pub expires_at: Option<i64>,
+    pub json: String,
+}
+
+impl StoredSession {
+    pub fn new(session: &Session) -> Result<Self> {
+        Ok(Self {
+            id: session.id().to_owned(),
+            user: session_user(session),
+            expires_at: session.expiry().map(|expiry| expiry.timestamp()),
+            json: serde_json::to_string(session)?,
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now())
+    }
+
+    /// The session, or `None` once it has expired
+    pub fn load(&self) -> Result<Option<Session>> {
+        Ok(serde_json::from_str::<Session>(&self.json)?.validate())
+    }
+}
+
+pub(crate) fn now() -> i64 {
+    Utc::now().timestamp()
+}
+
+/// Behaviour every store must share, run against each implementation
+#[cfg(test)]
+pub(crate) mod conformance {
+    use super::*;
+    use async_session::chrono::Duration;
+
+    fn user_session(id: &str) -> Session {
+        let mut session = Session::new();
+        session
+            .insert(
+                "user",
+                User {
+                    id: id.to_owned(),
+                    email: format!("{id}@example.com"),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        session.expire_in(std::time::Duration::from_secs(60));
+        session
+    }
+
+    async fn store<S: SessionStore>(store: &S, session: Session) -> String {
+        store.store_session(session).await.unwrap().unwrap()
+    }
+
+    pub async fn run<S: UserSessions>(store: &S) {
+        store.clear_store().await.unwrap();
+
+        let cookie = self::store(store, user_session("a")).await;
+        let loaded = store.load_session(cookie.clone()).await.unwrap().unwrap();
+        assert_eq!(session_user(&loaded).as_deref(), Some("a"));
+        assert!(loaded.expiry().is_some());
+
+        let mut anonymous = Session::new();
+        anonymous.insert("theme", "dark").unwrap();
+        let anonymous = self::store(store, anonymous).await;
+        let loaded_anonymous = store.load_session(anonymous.clone()).await.unwrap().unwrap();
+        assert_eq!(loaded_anonymous.get::<String>("theme").as_deref(), Some("dark"));
+
+        let unknown = Session::new().into_cookie_value().unwrap();
+        assert!(store.load_session(unknown).await.unwrap().is_none());
+
+        let mut expired = user_session("a");
+        expired.set_expiry(Utc::now() - Duration::seconds(5));
+        let expired_id = expired.id().to_owned();
+        let expired = self::store(store, expired).await;
+        assert!(store.load_session(expired).await.unwrap().is_none());
+
+        let other = self::store(store, user_session("a")).await;
+        let ids = store.user_sessions("a").await.unwrap();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&loaded.id().to_owned()));
+        assert!(!ids.contains(&expired_id));
+
+        store.destroy_session(loaded).await.unwrap();
+        assert!(store.load_session(cookie).await.unwrap().is_none());
+        assert_eq!(store.user_sessions("a").await.unwrap().len(), 1);
+
+        self::store(store, user_session("b")).await;
+        store.destroy_user_sessions("a").await.unwrap();
+        assert!(store.load_session(other).await.unwrap().is_none());
+        assert!(store.user_sessions("a").await.unwrap().is_empty());
+        assert_eq!(store.user_sessions("b").await.unwrap().len(), 1);
+
+        store.clear_store().await.unwrap();
+        assert!(store.load_session(anonymous).await.unwrap().is_none());
+        assert!(store.user_sessions("b").await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,185 @@
+use async_session::{async_trait, Result, Session, SessionStore};
+use redis::{aio::ConnectionManager, AsyncCommands};
+
+use super::{now, session_user, StoredSession, UserSessions};
+
+const KEY_PREFIX: &str = "oath:";
+
+/// Sessions in Redis, e.g. ElastiCache next to the functions in a VPC.
+///
+/// Each session is a string key that Redis expires at `Session::expiry`. A user's sessions
+/// are a sorted set of ids scored by their expiry, which itself expires with the last one.
+/// The connection manager reconnects on failure and is shared by clones, so one store
+/// built at cold start serves every warm invocation.
+#[derive(Clone)]
+pub struct RedisSessionStore {
+    conn: ConnectionManager,
+    prefix: String,
+}
+
+impl std::fmt::Debug for RedisSessionStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RedisSessionStore")
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RedisSessionStore {
+    /// Connect to `url`, e.g. `redis://localhost:6379` or `rediss://` for TLS
+    pub async fn new(url: &str) -> Result<Self> {
+        let client = redis::Client::open(url)?;
+        Ok(Self {
+            conn: ConnectionManager::new(client).await?,
+            prefix: String::from(KEY_PREFIX),
+        })
+    }
+
+    /// Namespace for all keys of this store, `oath:` by default
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_owned();
+        self
+    }
+
+    fn session_key(&self, id: &str) -> String {
+        format!("{}session:{id}", self.prefix)
+    }
+
+    fn user_key(&self, user: &str) -> String {
+        format!("{}user:{user}:sessions", self.prefix)
+    }
+
+    /// Drop expired ids from the user's set and let the set expire with its last session
+    async fn refresh_user_key(&self, key: &str) -> Result {
+        let mut conn = self.conn.clone();
+        conn.zrembyscore::<_, _, _, ()>(key, "-inf", now()).await?;
+        let last = conn
+            .zrange_withscores::<_, Vec<(String, f64)>>(key, -1, -1)
+            .await?;
+        match last.first() {
+            Some((_, score)) if score.is_finite() => {
+                conn.expire_at::<_, ()>(key, *score as usize).await?
+            }
+            Some(_) => conn.persist::<_, ()>(key).await?,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SessionStore for RedisSessionStore {
+    async fn load_session(&self, cookie_value: String) -> Result<Option<Session>> {
+        let id = Session::id_from_cookie_value(&cookie_value)?;
+        tracing::info!("loading session by id `{}`", id);
+
+        let mut conn = self.conn.clone();
+        let json = conn.get::<_, Option<String>>(self.session_key(&id)).await?;
+        match json {
+            Some(json) => StoredSession {
+                id,
+                user: None,
+                expires_at: None,
+                json,
+            }
+            .load(),
+            None => Ok(None),
+        }
+    }
+
+    async fn store_session(&self, session: Session) -> Result<Option<String>> {
+        tracing::info!("storing session by id `{}`", session.id());
+        let stored = StoredSession::new(&session)?;
+        let key = self.session_key(&stored.id);
+
+        let mut pipe = redis::pipe();
+        pipe.atomic().set(&key, &stored.json).ignore();
+        if let Some(expires_at) = stored.expires_at {
+            pipe.expire_at(&key, expires_at.max(0) as usize).ignore();
+        }
+        if let Some(user) = &stored.user {
+            let score = stored.expires_at.map_or(f64::INFINITY, |expires_at| expires_at as f64);
+            pipe.zadd(self.user_key(user), &stored.id, score).ignore();
+        }
+        pipe.query_async::<_, ()>(&mut self.conn.clone()).await?;
+
+        if let Some(user) = &stored.user {
+            self.refresh_user_key(&self.user_key(user)).await?;
+        }
+
+        session.reset_data_changed();
+        Ok(session.into_cookie_value())
+    }
+
+    async fn destroy_session(&self, session: Session) -> Result {
+        tracing::info!("destroying session by id `{}`", session.id());
+        let mut pipe = redis::pipe();
+        pipe.atomic().del(self.session_key(session.id())).ignore();
+        if let Some(user) = session_user(&session) {
+            pipe.zrem(self.user_key(&user), session.id()).ignore();
+        }
+        pipe.query_async::<_, ()>(&mut self.conn.clone()).await?;
+        Ok(())
+    }
+
+    async fn clear_store(&self) -> Result {
+        tracing::info!("clearing session store");
+        let mut conn = self.conn.clone();
+        let keys = {
+            let mut iter = conn
+                .scan_match::<_, String>(format!("{}*", self.prefix))
+                .await?;
+            let mut keys = vec![];
+            while let Some(key) = iter.next_item().await {
+                keys.push(key);
+            }
+            keys
+        };
+        for chunk in keys.chunks(100) {
+            self.conn.clone().del::<_, ()>(chunk).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UserSessions for RedisSessionStore {
+    async fn user_sessions(&self, user: &str) -> Result<Vec<String>> {
+        let mut conn = self.conn.clone();
+        Ok(conn
+            .zrangebyscore(self.user_key(user), format!("({}", now()), "+inf")
+            .await?)
+    }
+
+    async fn destroy_user_sessions(&self, user: &str) -> Result {
+        tracing::info!("destroying all sessions of `{}`", user);
+        let user_key = self.user_key(user);
+        let mut conn = self.conn.clone();
+        let ids = conn.zrange::<_, Vec<String>>(&user_key, 0, -1).await?;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for id in &ids {
+            pipe.del(self.session_key(id)).ignore();
+        }
+        pipe.del(&user_key).ignore();
+        pipe.query_async::<_, ()>(&mut conn).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "requires redis-server at REDIS_URL"]
+    async fn conformance() {
+        let url = std::env::var("REDIS_URL").unwrap_or(String::from("redis://127.0.0.1:6379"));
+        let store = RedisSessionStore::new(&url)
+            .await
+            .unwrap()
+            .with_prefix("oath-test:");
+        super::super::conformance::run(&store).await;
+    }
+}
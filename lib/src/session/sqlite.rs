@@ -0,0 +1,148 @@
+use async_session::{async_trait, Result, Session, SessionStore};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use super::{now, StoredSession, UserSessions};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS sessions (
+        id TEXT PRIMARY KEY NOT NULL,
+        user TEXT,
+        expires_at INTEGER,
+        session TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS sessions_user ON sessions (user);
+";
+
+/// Sessions in a SQLite database, for local development that survives restarts.
+///
+/// Queries run on the calling task, which is fine for a local file but not meant for
+/// production traffic.
+#[derive(Debug, Clone)]
+pub struct SqliteSessionStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteSessionStore {
+    /// Open or create the database at `path`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    pub fn in_memory() -> Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Delete expired sessions, returns how many were removed
+    pub fn sweep(&self) -> Result<usize> {
+        let removed = self
+            .conn()
+            .execute("DELETE FROM sessions WHERE expires_at <= ?1", params![now()])?;
+        tracing::info!("swept {} expired sessions", removed);
+        Ok(removed)
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[async_trait]
+impl SessionStore for SqliteSessionStore {
+    async fn load_session(&self, cookie_value: String) -> Result<Option<Session>> {
+        let id = Session::id_from_cookie_value(&cookie_value)?;
+        tracing::info!("loading session by id `{}`", id);
+
+        let stored = self
+            .conn()
+            .query_row(
+                "SELECT user, expires_at, session FROM sessions WHERE id = ?1",
+                params![id],
+                |row| {
+                    Ok(StoredSession {
+                        id: id.clone(),
+                        user: row.get(0)?,
+                        expires_at: row.get(1)?,
+                        json: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?;
+        match stored {
+            Some(stored) => stored.load(),
+            None => Ok(None),
+        }
+    }
+
+    async fn store_session(&self, session: Session) -> Result<Option<String>> {
+        tracing::info!("storing session by id `{}`", session.id());
+        let stored = StoredSession::new(&session)?;
+
+        self.conn().execute(
+            "INSERT INTO sessions (id, user, expires_at, session) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (id) DO UPDATE SET
+                user = excluded.user,
+                expires_at = excluded.expires_at,
+                session = excluded.session",
+            params![stored.id, stored.user, stored.expires_at, stored.json],
+        )?;
+
+        session.reset_data_changed();
+        Ok(session.into_cookie_value())
+    }
+
+    async fn destroy_session(&self, session: Session) -> Result {
+        tracing::info!("destroying session by id `{}`", session.id());
+        self.conn()
+            .execute("DELETE FROM sessions WHERE id = ?1", params![session.id()])?;
+        Ok(())
+    }
+
+    async fn clear_store(&self) -> Result {
+        tracing::info!("clearing session store");
+        self.conn().execute("DELETE FROM sessions", [])?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UserSessions for SqliteSessionStore {
+    async fn user_sessions(&self, user: &str) -> Result<Vec<String>> {
+        let conn = self.conn();
+        let mut statement = conn.prepare(
+            "SELECT id FROM sessions
+             WHERE user = ?1 AND (expires_at IS NULL OR expires_at > ?2)",
+        )?;
+        let ids = statement
+            .query_map(params![user, now()], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(ids)
+    }
+
+    async fn destroy_user_sessions(&self, user: &str) -> Result {
+        tracing::info!("destroying all sessions of `{}`", user);
+        self.conn()
+            .execute("DELETE FROM sessions WHERE user = ?1", params![user])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn conformance() {
+        super::super::conformance::run(&SqliteSessionStore::in_memory().unwrap()).await;
+    }
+}
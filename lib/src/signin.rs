@@ -0,0 +1,307 @@
+//! Who may sign in by email: exact addresses, domains and `*` wildcards, allowed or denied,
+//! from the environment and optionally a DynamoDB list admins edit without a redeploy.
+
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{aws::dynamodb::DbClient, error::Error};
+
+const RULE_PK: &str = "SIGNIN_RULE";
+const RULES_TTL: Duration = Duration::from_secs(60);
+
+/// An email, a domain, or either with `*` wildcards, e.g. `ann@ourco.com`, `ourco.com`,
+/// `*.ourco.com` or `contractor-*@gmail.com`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern(String);
+
+impl Pattern {
+    pub fn parse(pattern: &str) -> Option<Self> {
+        let pattern = pattern.trim().trim_start_matches('@').to_lowercase();
+        (!pattern.is_empty()).then_some(Self(pattern))
+    }
+
+    pub fn matches(&self, email: &str) -> bool {
+        let email = email.trim().to_lowercase();
+        match self.0.contains('@') {
+            true => glob(&self.0, &email),
+            false => email
+                .rsplit_once('@')
+                .is_some_and(|(_, domain)| glob(&self.0, domain)),
+        }
+    }
+}
+
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Whether `text` matches `pattern`, where `*` stands for any run of characters
+fn glob(pattern: &str, text: &str) -> bool {
+    let Some((first, rest)) = pattern.split_once('*') else {
+        return pattern == text;
+    };
+    let Some(mut text) = text.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts = rest.split('*').peekable();
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            return text.len() >= part.len() && text.ends_with(part);
+        }
+        match text.find(part) {
+            Some(index) => text = &text[index + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SignInRules {
+    /// Anyone not denied may sign in when empty
+    pub allow: Vec<Pattern>,
+    /// Wins over `allow`
+    pub deny: Vec<Pattern>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// With the allow rule that matched, `None` when there is no allow list
+    Allow(Option<Pattern>),
+    /// With the deny rule that matched, `None` when no allow rule did
+    Deny(Option<Pattern>),
+}
+
+impl SignInRules {
+    /// Comma separated patterns
+    pub fn parse(allow: &str, deny: &str) -> Self {
+        let list = |value: &str| value.split(',').filter_map(Pattern::parse).collect();
+        Self {
+            allow: list(allow),
+            deny: list(deny),
+        }
+    }
+
+    pub fn evaluate(&self, email: &str) -> Decision {
+        if let Some(rule) = self.deny.iter().find(|rule| rule.matches(email)) {
+            return Decision::Deny(Some(rule.clone()));
+        }
+        if self.allow.is_empty() {
+            return Decision::Allow(None);
+        }
+        match self.allow.iter().find(|rule| rule.matches(email)) {
+            Some(rule) => Decision::Allow(Some(rule.clone())),
+            None => Decision::Deny(None),
+        }
+    }
+
+    fn extend(&mut self, other: SignInRules) {
+        self.allow.extend(other.allow);
+        self.deny.extend(other.deny);
+    }
+}
+
+/// A rule item, `PK=SIGNIN_RULE` and `SK=allow#<pattern>` or `SK=deny#<pattern>`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct DynamoSignInRule {
+    #[serde(rename = "PK")]
+    pk: String,
+    #[serde(rename = "SK")]
+    sk: String,
+}
+
+#[derive(Debug)]
+struct Cached {
+    rules: SignInRules,
+    loaded_at: Instant,
+}
+
+/// The sign in rules from the environment, plus those in DynamoDB when configured
+#[derive(Debug, Clone, Default)]
+pub struct SignInPolicy {
+    rules: SignInRules,
+    table: Option<Arc<DbClient>>,
+    ttl: Duration,
+    cache: Arc<RwLock<Option<Cached>>>,
+}
+
+impl SignInPolicy {
+    pub fn new(rules: SignInRules) -> Self {
+        Self {
+            rules,
+            ttl: RULES_TTL,
+            ..Self::default()
+        }
+    }
+
+    /// Also apply the rules stored in `db`, reloaded at most every `ttl`
+    pub fn with_table(mut self, db: Arc<DbClient>, ttl: Duration) -> Self {
+        self.table = Some(db);
+        self.ttl = ttl;
+        self
+    }
+
+    /// `SIGNIN_ALLOW` and `SIGNIN_DENY`, and with `SIGNIN_RULES=dynamo` the rules in
+    /// `TABLE_NAME`, cached for `SIGNIN_RULES_TTL_SECS` (60 by default)
+    pub async fn from_env() -> Result<Self, Error> {
+        let var = |name: &str| std::env::var(name).unwrap_or_default();
+        let policy = Self::new(SignInRules::parse(
+            &var("SIGNIN_ALLOW"),
+            &var("SIGNIN_DENY"),
+        ));
+        match var("SIGNIN_RULES").as_str() {
+            "" | "env" => Ok(policy),
+            "dynamo" => {
+                let Ok(table_name) = std::env::var("TABLE_NAME") else {
+                    return Err(Error::config("ENV VAR TABLE_NAME not set"));
+                };
+                let ttl = var("SIGNIN_RULES_TTL_SECS")
+                    .parse::<u64>()
+                    .map_or(RULES_TTL, Duration::from_secs);
+                Ok(policy.with_table(DbClient::new(&table_name).await, ttl))
+            }
+            other => Err(Error::config(format!(
+                "SIGNIN_RULES `{other}` is not env or dynamo"
+            ))),
+        }
+    }
+
+    /// Decide and log whether `email` may sign in
+    pub async fn evaluate(&self, email: &str) -> Result<Decision, Error> {
+        let decision = match &self.table {
+            Some(db) => {
+                let mut rules = self.rules.clone();
+                rules.extend(self.stored_rules(db).await?);
+                rules.evaluate(email)
+            }
+            None => self.rules.evaluate(email),
+        };
+        match &decision {
+            Decision::Allow(rule) => tracing::info!(
+                "sign in allowed for `{}` by {}",
+                email,
+                rule.as_ref()
+                    .map_or(String::from("no allow list"), |rule| format!("`{rule}`"))
+            ),
+            Decision::Deny(rule) => tracing::info!(
+                "sign in denied for `{}` by {}",
+                email,
+                rule.as_ref()
+                    .map_or(String::from("the allow list"), |rule| format!("`{rule}`"))
+            ),
+        }
+        Ok(decision)
+    }
+
+    async fn stored_rules(&self, db: &DbClient) -> Result<SignInRules, Error> {
+        if let Some(cached) = self.read().as_ref() {
+            if cached.loaded_at.elapsed() < self.ttl {
+                return Ok(cached.rules.clone());
+            }
+        }
+
+        let items = db
+            .query_single_table::<DynamoSignInRule>(String::from(RULE_PK), None, None)
+            .await?;
+        let mut rules = SignInRules::default();
+        for item in items {
+            match item.sk.split_once('#') {
+                Some(("allow", pattern)) => rules.allow.extend(Pattern::parse(pattern)),
+                Some(("deny", pattern)) => rules.deny.extend(Pattern::parse(pattern)),
+                _ => tracing::warn!("ignoring sign in rule `{}`", item.sk),
+            }
+        }
+        *self
+            .cache
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Cached {
+            rules: rules.clone(),
+            loaded_at: Instant::now(),
+        });
+        Ok(rules)
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Option<Cached>> {
+        self.cache
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, email: &str) -> bool {
+        Pattern::parse(pattern).unwrap().matches(email)
+    }
+
+    #[test]
+    fn matches_patterns() {
+        assert!(matches("Ann@OurCo.com", "ann@ourco.com"));
+        assert!(!matches("ann@ourco.com", "anne@ourco.com"));
+        assert!(matches("ourco.com", "bob@OurCo.com"));
+        assert!(matches("@ourco.com", "bob@ourco.com"));
+        assert!(!matches("ourco.com", "bob@eng.ourco.com"));
+        assert!(matches("*.ourco.com", "bob@eng.ourco.com"));
+        assert!(!matches("*.ourco.com", "bob@ourco.com"));
+        assert!(matches(
+            "contractor-*@gmail.com",
+            "contractor-ann@gmail.com"
+        ));
+        assert!(!matches("contractor-*@gmail.com", "ann@gmail.com"));
+        assert!(matches("*a*b*@*", "xaxxbx@c"));
+        assert!(!matches("a*a", "a"));
+    }
+
+    #[test]
+    fn denies_before_allowing() {
+        let rules = SignInRules::parse("ourco.com, ann@gmail.com", "intern-*@ourco.com");
+        assert_eq!(
+            rules.evaluate("bob@ourco.com"),
+            Decision::Allow(Pattern::parse("ourco.com"))
+        );
+        assert!(matches!(
+            rules.evaluate("ann@gmail.com"),
+            Decision::Allow(_)
+        ));
+        assert_eq!(
+            rules.evaluate("intern-cy@ourco.com"),
+            Decision::Deny(Pattern::parse("intern-*@ourco.com"))
+        );
+        assert_eq!(rules.evaluate("eve@gmail.com"), Decision::Deny(None));
+
+        let open = SignInRules::parse("", "gmail.com");
+        assert_eq!(open.evaluate("bob@ourco.com"), Decision::Allow(None));
+        assert!(matches!(open.evaluate("eve@gmail.com"), Decision::Deny(_)));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires DynamoDB Local at DYNAMODB_ENDPOINT and TABLE_NAME"]
+    async fn loads_stored_rules() {
+        let table_name = std::env::var("TABLE_NAME").unwrap();
+        let db = DbClient::new(&table_name).await;
+        db.put(DynamoSignInRule {
+            pk: String::from(RULE_PK),
+            sk: String::from("deny#eve@ourco.com"),
+        })
+        .await
+        .unwrap();
+
+        let policy = SignInPolicy::new(SignInRules::parse("ourco.com", ""))
+            .with_table(db.clone(), Duration::from_secs(60));
+        assert!(matches!(
+            policy.evaluate("eve@ourco.com").await.unwrap(),
+            Decision::Deny(_)
+        ));
+        db.delete(String::from(RULE_PK), String::from("deny#eve@ourco.com"))
+            .await
+            .unwrap();
+    }
+}
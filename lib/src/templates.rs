@@ -0,0 +1,210 @@
+use std::sync::OnceLock;
+
+use askama::Template;
+
+use crate::error::Error;
+
+const DEFAULT_PRIMARY_COLOR: &str = "#24292f";
+const DEFAULT_BACKGROUND_COLOR: &str = "#f6f8fa";
+const DEFAULT_TEXT_COLOR: &str = "#1f2328";
+
+/// Look and feel shared by every page
+#[derive(Debug, Clone)]
+pub struct Branding {
+    pub app_name: String,
+    pub logo_url: Option<String>,
+    pub primary_color: String,
+    pub background_color: String,
+    pub text_color: String,
+}
+
+impl Default for Branding {
+    fn default() -> Self {
+        Self {
+            app_name: String::from("Oath"),
+            logo_url: None,
+            primary_color: String::from(DEFAULT_PRIMARY_COLOR),
+            background_color: String::from(DEFAULT_BACKGROUND_COLOR),
+            text_color: String::from(DEFAULT_TEXT_COLOR),
+        }
+    }
+}
+
+impl Branding {
+    /// Read `BRAND_APP_NAME`, `BRAND_LOGO_URL` and `BRAND_{PRIMARY,BACKGROUND,TEXT}_COLOR`.
+    ///
+    /// Colors end up inside a `<style>` block, so anything but a hex color is ignored;
+    /// the logo must be an http(s) URL.
+    pub fn from_env() -> Self {
+        let default = Self::default();
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+        let color = |name: &str, default: String| match var(name) {
+            Some(color) if is_hex_color(&color) => color,
+            Some(color) => {
+                tracing::warn!("ignoring invalid color {name}=`{color}`");
+                default
+            }
+            None => default,
+        };
+
+        Self {
+            app_name: var("BRAND_APP_NAME").unwrap_or(default.app_name),
+            logo_url: var("BRAND_LOGO_URL").filter(|url| {
+                let valid = url.starts_with("https://") || url.starts_with("http://");
+                if !valid {
+                    tracing::warn!("ignoring invalid BRAND_LOGO_URL `{url}`");
+                }
+                valid
+            }),
+            primary_color: color("BRAND_PRIMARY_COLOR", default.primary_color),
+            background_color: color("BRAND_BACKGROUND_COLOR", default.background_color),
+            text_color: color("BRAND_TEXT_COLOR", default.text_color),
+        }
+    }
+
+    /// Branding from the environment, read once per cold start
+    pub fn global() -> &'static Branding {
+        static BRANDING: OnceLock<Branding> = OnceLock::new();
+        BRANDING.get_or_init(Branding::from_env)
+    }
+}
+
+fn is_hex_color(color: &str) -> bool {
+    match color.strip_prefix('#') {
+        Some(hex) => matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit()),
+        None => false,
+    }
+}
+
+/// A sign in option on the login page
+#[derive(Debug, Clone)]
+pub struct ProviderLink {
+    pub label: String,
+    pub start_url: String,
+}
+
+#[derive(Template)]
+#[template(path = "login.html")]
+pub struct LoginPage<'a> {
+    pub branding: &'a Branding,
+    pub providers: Vec<ProviderLink>,
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+pub struct ErrorPage<'a> {
+    pub branding: &'a Branding,
+    pub status: u16,
+    pub title: &'a str,
+    pub message: &'a str,
+}
+
+#[derive(Template)]
+#[template(path = "logged_out.html")]
+pub struct LoggedOutPage<'a> {
+    pub branding: &'a Branding,
+    pub login_url: String,
+}
+
+#[derive(Template)]
+#[template(path = "landing.html")]
+pub struct LandingPage<'a> {
+    pub branding: &'a Branding,
+    pub email: &'a str,
+    pub logout_url: Option<String>,
+}
+
+/// Shown when a provider account is not in the orgs or teams allowed to sign in
+#[derive(Template)]
+#[template(path = "access_denied.html")]
+pub struct AccessDeniedPage<'a> {
+    pub branding: &'a Branding,
+    /// e.g. `GitHub Enterprise`
+    pub provider: &'a str,
+    pub login: &'a str,
+    pub requirement: &'a str,
+    pub login_url: String,
+}
+
+/// Shown when a provider account has no email the email policy accepts
+#[derive(Template)]
+#[template(path = "no_email.html")]
+pub struct NoEmailPage<'a> {
+    pub branding: &'a Branding,
+    pub provider: &'a str,
+    pub login: &'a str,
+    /// e.g. `a verified primary email address`
+    pub requirement: &'a str,
+    pub settings_url: String,
+    pub login_url: String,
+}
+
+pub fn render(template: &impl Template) -> Result<String, Error> {
+    template
+        .render()
+        .map_err(|err| Error::internal("failed to render template").with_source(err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_user_input() {
+        let branding = Branding::default();
+        let page = render(&LandingPage {
+            branding: &branding,
+            email: "<script>alert(1)</script>@example.com",
+            logout_url: None,
+        })
+        .unwrap();
+        assert!(page.contains("&lt;script&gt;alert(1)&lt;/script&gt;@example.com"));
+        assert!(!page.contains("<script>"));
+    }
+
+    #[test]
+    fn renders_branding() {
+        let branding = Branding {
+            app_name: String::from("Acme \"SSO\""),
+            logo_url: Some(String::from("https://acme.test/logo.png")),
+            ..Branding::default()
+        };
+        let page = render(&LoginPage {
+            branding: &branding,
+            providers: vec![ProviderLink {
+                label: String::from("GitHub"),
+                start_url: String::from("/Prod/login/github/start"),
+            }],
+        })
+        .unwrap();
+        assert!(page.contains("Acme &quot;SSO&quot;"));
+        assert!(page.contains(r#"src="https://acme.test/logo.png""#));
+        assert!(page.contains("Continue with GitHub"));
+        assert!(page.contains(DEFAULT_PRIMARY_COLOR));
+    }
+
+    #[test]
+    fn explains_missing_emails() {
+        let branding = Branding::default();
+        let page = render(&NoEmailPage {
+            branding: &branding,
+            provider: "GitHub",
+            login: "<octo>",
+            requirement: "a verified primary email address",
+            settings_url: String::from("https://github.com/settings/emails"),
+            login_url: String::from("/Prod/login"),
+        })
+        .unwrap();
+        assert!(page.contains("&lt;octo&gt;"));
+        assert!(page.contains("a verified primary email address"));
+        assert!(page.contains(r#"href="https://github.com/settings/emails""#));
+    }
+
+    #[test]
+    fn validates_colors() {
+        assert!(is_hex_color("#fff"));
+        assert!(is_hex_color("#0a0B0c"));
+        assert!(!is_hex_color("red"));
+        assert!(!is_hex_color("#fff;} body{display:none"));
+    }
+}
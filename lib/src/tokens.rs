@@ -0,0 +1,497 @@
+//! The token vault: provider access and refresh tokens kept per user, so handlers can call
+//! a provider on the user's behalf, sealed with envelope encryption in DynamoDB.
+
+use aws_sdk_dynamodb::types::AttributeValue;
+use lru::LruCache;
+use oauth2::{TokenResponse, TokenType};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    aws::dynamodb::DbClient,
+    envelope::{Envelope, KeyProvider, Sealed},
+    error::Error,
+    oauth::Scopes,
+    session::now,
+    users::identity_key,
+};
+
+const TOKEN_SK_PREFIX: &str = "TOKEN#";
+/// Tokens this close to expiring are refreshed before use
+const EXPIRY_MARGIN_SECS: i64 = 60;
+const GRANT_CACHE_CAPACITY: usize = 1024;
+const GRANT_CACHE_TTL: Duration = Duration::from_secs(10);
+
+/// The grant of one of a user's provider identities
+#[derive(Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProviderToken {
+    pub provider: String,
+    /// The identity's subject, see `crate::users::LinkedIdentity`
+    pub subject: String,
+    pub access_token: String,
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    /// Unix seconds, `None` for tokens that do not expire
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    #[serde(default)]
+    pub scopes: Scopes,
+}
+
+impl ProviderToken {
+    /// `<provider>:<subject>`, the identity the token acts as
+    pub fn identity(&self) -> String {
+        identity_key(&self.provider, &self.subject)
+    }
+
+    /// The grant of a token response, for the `provider` account `subject`, see
+    /// `Scopes::granted` for `requested`
+    pub fn from_response<TT: TokenType>(
+        provider: &str,
+        subject: &str,
+        response: &impl TokenResponse<TT>,
+        requested: &Scopes,
+    ) -> Self {
+        Self {
+            provider: provider.to_owned(),
+            subject: subject.to_owned(),
+            access_token: response.access_token().secret().to_owned(),
+            refresh_token: response
+                .refresh_token()
+                .map(|refresh_token| refresh_token.secret().to_owned()),
+            expires_at: expires_at(response),
+            scopes: Scopes::granted(response, requested),
+        }
+    }
+
+    /// The token a refresh returned, keeping the refresh token and scopes the response
+    /// leaves out
+    pub fn refreshed<TT: TokenType>(self, response: &impl TokenResponse<TT>) -> Self {
+        let token = Self::from_response(&self.provider, &self.subject, response, &self.scopes);
+        Self {
+            refresh_token: token.refresh_token.or(self.refresh_token),
+            ..token
+        }
+    }
+
+    /// Expired, or about to, at `now` in unix seconds
+    pub fn is_expired(&self, now: i64) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| expires_at - EXPIRY_MARGIN_SECS <= now)
+    }
+}
+
+impl std::fmt::Debug for ProviderToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ProviderToken")
+            .field("provider", &self.provider)
+            .field("subject", &self.subject)
+            .field("refreshable", &self.refresh_token.is_some())
+            .field("expires_at", &self.expires_at)
+            .field("scopes", &self.scopes)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A token item, `PK=u#<user>` and `SK=TOKEN#<provider>:<subject>`, sealed with its key
+/// as associated data so it cannot be moved to another user
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct DynamoToken {
+    #[serde(rename = "PK")]
+    pk: String,
+    #[serde(rename = "SK")]
+    sk: String,
+    sealed: Sealed,
+}
+
+fn token_key(user_id: &str, identity: &str) -> (String, String) {
+    (
+        format!("u#{user_id}"),
+        format!("{TOKEN_SK_PREFIX}{identity}"),
+    )
+}
+
+fn aad(pk: &str, sk: &str) -> Vec<u8> {
+    format!("{pk}/{sk}").into_bytes()
+}
+
+/// Tokens by `(PK, SK)`, as they would be keyed in DynamoDB
+type Tokens = RwLock<HashMap<(String, String), ProviderToken>>;
+
+#[derive(Debug, Clone, Default)]
+enum Backend {
+    /// Tokens are dropped after sign in
+    #[default]
+    Off,
+    Memory(Arc<Tokens>),
+    Dynamo {
+        db: Arc<DbClient>,
+        envelope: Envelope,
+    },
+}
+
+/// When `TokenVault::has` last found a grant's token
+#[derive(Debug)]
+struct FoundGrants {
+    cache: Mutex<LruCache<(String, String), Instant>>,
+    ttl: Duration,
+}
+
+impl FoundGrants {
+    fn contains(&self, key: &(String, String)) -> bool {
+        let mut cache = self.lock();
+        match cache.get(key) {
+            Some(found_at) if found_at.elapsed() < self.ttl => true,
+            Some(_) => {
+                cache.pop(key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, LruCache<(String, String), Instant>> {
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Where provider tokens are kept, off by default
+#[derive(Debug, Clone, Default)]
+pub struct TokenVault {
+    backend: Backend,
+    found: Option<Arc<FoundGrants>>,
+}
+
+impl TokenVault {
+    /// Tokens in process memory, for local development and tests
+    pub fn memory() -> Self {
+        Self {
+            backend: Backend::Memory(Arc::default()),
+            found: None,
+        }
+    }
+
+    /// Tokens in `db`, sealed by `envelope`
+    pub fn dynamo(db: Arc<DbClient>, envelope: Envelope) -> Self {
+        Self {
+            backend: Backend::Dynamo { db, envelope },
+            found: None,
+        }
+    }
+
+    /// Tokens in `TABLE_NAME` when `TOKEN_ENCRYPTION` names a key provider, as
+    /// `KeyProvider::from_env_prefixed("TOKEN")` reads it, and off when it is unset
+    pub async fn from_env() -> Result<Self, Error> {
+        let Some(provider) = KeyProvider::from_env_prefixed("TOKEN").await? else {
+            tracing::info!("TOKEN_ENCRYPTION not set, provider tokens are not kept");
+            return Ok(Self::default());
+        };
+        let Ok(table_name) = std::env::var("TABLE_NAME") else {
+            return Err(Error::config("ENV VAR TABLE_NAME not set"));
+        };
+        Ok(Self::dynamo(
+            DbClient::new(&table_name).await,
+            Envelope::new(provider),
+        ))
+    }
+
+    /// Remember the grants `has` found for `ttl`, e.g. in `auth_fn` between warm invocations,
+    /// so a session that follows a grant costs a table read once per TTL rather than per
+    /// request. Deleting through this vault forgets the grant, but other instances keep
+    /// accepting it until their entry expires, as `crate::session::CachedSessionStore` does.
+    pub fn with_grant_cache(mut self, ttl: Duration) -> Self {
+        let capacity = NonZeroUsize::new(GRANT_CACHE_CAPACITY).expect("capacity is not zero");
+        self.found = Some(Arc::new(FoundGrants {
+            cache: Mutex::new(LruCache::new(capacity)),
+            ttl,
+        }));
+        self
+    }
+
+    /// `with_grant_cache` for `TOKEN_CACHE_TTL_SECS`, 10 by default, zero disables it
+    pub fn with_grant_cache_from_env(self) -> Self {
+        let ttl = std::env::var("TOKEN_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|value| value.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(GRANT_CACHE_TTL);
+        match ttl.is_zero() {
+            true => self,
+            false => self.with_grant_cache(ttl),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self.backend, Backend::Off)
+    }
+
+    /// Keep `token` for `user_id`, replacing the identity's previous one
+    pub async fn put(&self, user_id: &str, token: &ProviderToken) -> Result<(), Error> {
+        let (pk, sk) = token_key(user_id, &token.identity());
+        match &self.backend {
+            Backend::Off => {}
+            Backend::Memory(tokens) => {
+                write(tokens).insert((pk, sk), token.clone());
+            }
+            Backend::Dynamo { db, envelope } => {
+                let json = serde_json::to_vec(token)
+                    .map_err(|err| Error::internal("failed to serialize token").with_source(err))?;
+                let sealed = envelope.seal(&json, &aad(&pk, &sk)).await?;
+                db.put(DynamoToken { pk, sk, sealed }).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Every token kept for `user_id`
+    pub async fn tokens(&self, user_id: &str) -> Result<Vec<ProviderToken>, Error> {
+        let (pk, _) = token_key(user_id, "");
+        match &self.backend {
+            Backend::Off => Ok(vec![]),
+            Backend::Memory(tokens) => Ok(read(tokens)
+                .iter()
+                .filter(|((token_pk, _), _)| *token_pk == pk)
+                .map(|(_, token)| token.clone())
+                .collect()),
+            Backend::Dynamo { db, envelope } => {
+                let items = db
+                    .query::<DynamoToken>(
+                        "#pk = :pk and begins_with(#sk, :sk)",
+                        HashMap::from([
+                            (String::from("#pk"), String::from("PK")),
+                            (String::from("#sk"), String::from("SK")),
+                        ]),
+                        HashMap::from([
+                            (String::from(":pk"), AttributeValue::S(pk)),
+                            (
+                                String::from(":sk"),
+                                AttributeValue::S(String::from(TOKEN_SK_PREFIX)),
+                            ),
+                        ]),
+                        None,
+                    )
+                    .await?;
+                let mut tokens = Vec::with_capacity(items.len());
+                for item in items {
+                    let json = envelope
+                        .open(&item.sealed, &aad(&item.pk, &item.sk))
+                        .await?;
+                    tokens.push(serde_json::from_slice(&json).map_err(|err| {
+                        Error::storage("failed to deserialize token").with_source(err)
+                    })?);
+                }
+                Ok(tokens)
+            }
+        }
+    }
+
+    /// The token of the user's first identity at `provider`
+    pub async fn token(
+        &self,
+        user_id: &str,
+        provider: &str,
+    ) -> Result<Option<ProviderToken>, Error> {
+        let mut tokens = self.tokens(user_id).await?;
+        tokens.sort_by(|a, b| a.subject.cmp(&b.subject));
+        Ok(tokens.into_iter().find(|token| token.provider == provider))
+    }
+
+    /// Whether a token of the identity is kept, without opening it, see `with_grant_cache`
+    pub async fn has(&self, user_id: &str, identity: &str) -> Result<bool, Error> {
+        let key = token_key(user_id, identity);
+        if self.found.as_ref().is_some_and(|found| found.contains(&key)) {
+            return Ok(true);
+        }
+        let has = match &self.backend {
+            Backend::Off => false,
+            Backend::Memory(tokens) => read(tokens).contains_key(&key),
+            Backend::Dynamo { db, .. } => !db
+                .query_single_table::<DynamoToken>(key.0.clone(), Some(key.1.clone()), None)
+                .await?
+                .is_empty(),
+        };
+        if let (true, Some(found)) = (has, &self.found) {
+            found.lock().put(key, Instant::now());
+        }
+        Ok(has)
+    }
+
+    /// Forget the token of one identity, e.g. `github:1234`
+    pub async fn delete(&self, user_id: &str, identity: &str) -> Result<(), Error> {
+        let (pk, sk) = token_key(user_id, identity);
+        if let Some(found) = &self.found {
+            found.lock().pop(&(pk.clone(), sk.clone()));
+        }
+        match &self.backend {
+            Backend::Off => {}
+            Backend::Memory(tokens) => {
+                write(tokens).remove(&(pk, sk));
+            }
+            Backend::Dynamo { db, .. } => {
+                db.delete(pk, sk).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget every token of `user_id`
+    pub async fn delete_all(&self, user_id: &str) -> Result<(), Error> {
+        for token in self.tokens(user_id).await? {
+            self.delete(user_id, &token.identity()).await?;
+        }
+        tracing::info!("deleted provider tokens of user `{}`", user_id);
+        Ok(())
+    }
+}
+
+fn read(
+    tokens: &Tokens,
+) -> std::sync::RwLockReadGuard<'_, HashMap<(String, String), ProviderToken>> {
+    tokens
+        .read()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn write(
+    tokens: &Tokens,
+) -> std::sync::RwLockWriteGuard<'_, HashMap<(String, String), ProviderToken>> {
+    tokens
+        .write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// When a token response's access token expires, in unix seconds
+fn expires_at<TT: TokenType>(response: &impl TokenResponse<TT>) -> Option<i64> {
+    response
+        .expires_in()
+        .map(|expires_in| now() + expires_in.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::AesKey;
+    use oauth2::basic::BasicTokenResponse;
+
+    fn token(provider: &str, subject: &str) -> ProviderToken {
+        ProviderToken {
+            provider: provider.to_owned(),
+            subject: subject.to_owned(),
+            access_token: format!("gho_{subject}"),
+            refresh_token: None,
+            expires_at: None,
+            scopes: Scopes::parse("user:email"),
+        }
+    }
+
+    async fn round_trip(vault: &TokenVault) {
+        vault.put("a", &token("github", "1")).await.unwrap();
+        vault.put("a", &token("ghe", "7")).await.unwrap();
+        vault.put("b", &token("github", "2")).await.unwrap();
+
+        let github = vault.token("a", "github").await.unwrap().unwrap();
+        assert_eq!(github.access_token, "gho_1");
+        assert_eq!(vault.tokens("a").await.unwrap().len(), 2);
+        assert!(vault.has("a", "ghe:7").await.unwrap());
+
+        vault.delete("a", "ghe:7").await.unwrap();
+        assert!(vault.token("a", "ghe").await.unwrap().is_none());
+        vault.delete_all("a").await.unwrap();
+        assert!(vault.tokens("a").await.unwrap().is_empty());
+        assert!(vault.token("b", "github").await.unwrap().is_some());
+        vault.delete_all("b").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_tokens_per_user() {
+        round_trip(&TokenVault::memory()).await;
+
+        let off = TokenVault::default();
+        off.put("a", &token("github", "1")).await.unwrap();
+        assert!(off.token("a", "github").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn caches_found_grants() {
+        let vault = TokenVault::memory().with_grant_cache(Duration::from_secs(60));
+        assert!(!vault.has("a", "github:1").await.unwrap());
+        vault.put("a", &token("github", "1")).await.unwrap();
+        assert!(vault.has("a", "github:1").await.unwrap());
+
+        // another instance deleting it is not seen until the entry expires
+        let Backend::Memory(tokens) = &vault.backend else {
+            unreachable!()
+        };
+        write(tokens).clear();
+        assert!(vault.has("a", "github:1").await.unwrap());
+
+        let expiring = TokenVault {
+            found: None,
+            ..vault.clone()
+        }
+        .with_grant_cache(Duration::ZERO);
+        assert!(!expiring.has("a", "github:1").await.unwrap());
+
+        vault.put("a", &token("github", "1")).await.unwrap();
+        assert!(vault.has("a", "github:1").await.unwrap());
+        vault.delete("a", "github:1").await.unwrap();
+        assert!(!vault.has("a", "github:1").await.unwrap());
+    }
+
+    #[test]
+    fn expires_early() {
+        let mut token = token("github", "1");
+        assert!(!token.is_expired(now()));
+        token.expires_at = Some(now() + 30);
+        assert!(token.is_expired(now()));
+        token.expires_at = Some(now() + 3600);
+        assert!(!token.is_expired(now()));
+        assert!(!format!("{token:?}").contains("gho_1"));
+    }
+
+    #[test]
+    fn refreshes_from_responses() {
+        let response: BasicTokenResponse = serde_json::from_str(
+            r#"{"access_token": "ghu_1", "token_type": "bearer", "expires_in": 28800,
+                "refresh_token": "ghr_1", "scope": ""}"#,
+        )
+        .unwrap();
+        let token = ProviderToken::from_response("github", "1", &response, &Scopes::default());
+        assert_eq!(token.refresh_token.as_deref(), Some("ghr_1"));
+        assert!(!token.is_expired(now() + 28000));
+        assert!(token.is_expired(now() + 28800));
+
+        let token = ProviderToken {
+            scopes: Scopes::parse("user:email"),
+            ..token
+        };
+        let response: BasicTokenResponse = serde_json::from_str(
+            r#"{"access_token": "ghu_2", "token_type": "bearer", "expires_in": 28800}"#,
+        )
+        .unwrap();
+        let refreshed = token.refreshed(&response);
+        assert_eq!(refreshed.access_token, "ghu_2");
+        assert_eq!(refreshed.refresh_token.as_deref(), Some("ghr_1"));
+        assert_eq!(refreshed.scopes, Scopes::parse("user:email"));
+    }
+
+    #[tokio::test]
+    #[ignore = "requires DynamoDB Local at DYNAMODB_ENDPOINT and TABLE_NAME"]
+    async fn seals_tokens_in_dynamo() {
+        let table_name = std::env::var("TABLE_NAME").unwrap();
+        let key = AesKey::new("k1", &[1; 32]).unwrap();
+        let envelope = Envelope::new(KeyProvider::Local(vec![key]));
+        round_trip(&TokenVault::dynamo(
+            DbClient::new(&table_name).await,
+            envelope,
+        ))
+        .await;
+    }
+}
@@ -0,0 +1,128 @@
+use async_session::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use super::{UserRecord, UserStore};
+use crate::{aws::dynamodb::DbClient, error::Result};
+
+const USER_SK: &str = "USER";
+const IDENTITY_PK: &str = "IDENTITY";
+const EMAIL_PK: &str = "EMAIL";
+
+/// The user item, in the user's partition next to their session index
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct DynamoUser {
+    #[serde(rename = "PK")]
+    pk: String,
+    #[serde(rename = "SK")]
+    sk: String,
+    #[serde(flatten)]
+    user: UserRecord,
+}
+
+/// Maps a key to its user: identities as `PK=IDENTITY` and `SK=<provider>:<subject>`,
+/// verified emails as `PK=EMAIL` and `SK=<lowercase email>`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct DynamoLink {
+    #[serde(rename = "PK")]
+    pk: String,
+    #[serde(rename = "SK")]
+    sk: String,
+    user_id: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct DynamoUserStore {
+    db: Arc<DbClient>,
+}
+
+impl DynamoUserStore {
+    pub fn new(db: Arc<DbClient>) -> Self {
+        Self { db }
+    }
+
+    async fn find_link(&self, pk: &str, key: &str) -> Result<Option<String>> {
+        let links = self
+            .db
+            .query_single_table::<DynamoLink>(String::from(pk), Some(key.to_owned()), None)
+            .await?;
+        Ok(links.into_iter().next().map(|link| link.user_id))
+    }
+
+    async fn link(&self, pk: &str, key: &str, user_id: &str) -> Result<bool> {
+        self.db
+            .put_if_absent(DynamoLink {
+                pk: String::from(pk),
+                sk: key.to_owned(),
+                user_id: user_id.to_owned(),
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl UserStore for DynamoUserStore {
+    async fn get_user(&self, id: &str) -> Result<Option<UserRecord>> {
+        let users = self
+            .db
+            .query_single_table::<DynamoUser>(
+                format!("u#{id}"),
+                Some(String::from(USER_SK)),
+                None,
+            )
+            .await?;
+        Ok(users.into_iter().next().map(|item| item.user))
+    }
+
+    async fn put_user(&self, user: &UserRecord) -> Result<()> {
+        self.db
+            .put(DynamoUser {
+                pk: format!("u#{}", user.id),
+                sk: String::from(USER_SK),
+                user: user.clone(),
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn find_identity(&self, identity: &str) -> Result<Option<String>> {
+        self.find_link(IDENTITY_PK, identity).await
+    }
+
+    async fn link_identity(&self, identity: &str, user_id: &str) -> Result<bool> {
+        self.link(IDENTITY_PK, identity, user_id).await
+    }
+
+    async fn unlink_identity(&self, identity: &str) -> Result<()> {
+        self.db
+            .delete(String::from(IDENTITY_PK), identity.to_owned())
+            .await?;
+        Ok(())
+    }
+
+    async fn find_email(&self, email: &str) -> Result<Option<String>> {
+        self.find_link(EMAIL_PK, email).await
+    }
+
+    async fn link_email(&self, email: &str, user_id: &str) -> Result<bool> {
+        self.link(EMAIL_PK, email, user_id).await
+    }
+
+    async fn unlink_email(&self, email: &str) -> Result<()> {
+        self.db.delete(String::from(EMAIL_PK), email.to_owned()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "requires DynamoDB Local at DYNAMODB_ENDPOINT and TABLE_NAME"]
+    async fn conformance() {
+        let table_name = std::env::var("TABLE_NAME").unwrap();
+        let store = DynamoUserStore::new(DbClient::new(&table_name).await);
+        super::super::tests::conformance(&store).await;
+    }
+}
@@ -0,0 +1,85 @@
+use async_session::async_trait;
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use super::{UserRecord, UserStore};
+use crate::error::Result;
+
+/// Users in process memory, for local development and tests
+#[derive(Debug, Clone, Default)]
+pub struct MemoryUserStore {
+    inner: Arc<RwLock<Inner>>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    users: HashMap<String, UserRecord>,
+    identities: HashMap<String, String>,
+    emails: HashMap<String, String>,
+}
+
+impl MemoryUserStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, Inner> {
+        self.inner.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, Inner> {
+        self.inner.write().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[async_trait]
+impl UserStore for MemoryUserStore {
+    async fn get_user(&self, id: &str) -> Result<Option<UserRecord>> {
+        Ok(self.read().users.get(id).cloned())
+    }
+
+    async fn put_user(&self, user: &UserRecord) -> Result<()> {
+        self.write().users.insert(user.id.clone(), user.clone());
+        Ok(())
+    }
+
+    async fn find_identity(&self, identity: &str) -> Result<Option<String>> {
+        Ok(self.read().identities.get(identity).cloned())
+    }
+
+    async fn link_identity(&self, identity: &str, user_id: &str) -> Result<bool> {
+        let mut inner = self.write();
+        if inner.identities.contains_key(identity) {
+            return Ok(false);
+        }
+        inner
+            .identities
+            .insert(identity.to_owned(), user_id.to_owned());
+        Ok(true)
+    }
+
+    async fn unlink_identity(&self, identity: &str) -> Result<()> {
+        self.write().identities.remove(identity);
+        Ok(())
+    }
+
+    async fn find_email(&self, email: &str) -> Result<Option<String>> {
+        Ok(self.read().emails.get(email).cloned())
+    }
+
+    async fn link_email(&self, email: &str, user_id: &str) -> Result<bool> {
+        let mut inner = self.write();
+        if inner.emails.contains_key(email) {
+            return Ok(false);
+        }
+        inner.emails.insert(email.to_owned(), user_id.to_owned());
+        Ok(true)
+    }
+
+    async fn unlink_email(&self, email: &str) -> Result<()> {
+        self.write().emails.remove(email);
+        Ok(())
+    }
+}
@@ -0,0 +1,494 @@
+//! Persisted users, keyed on an internal id that stays stable when the email at a
+//! provider changes, with the provider identities (`github:<numeric id>`) linked to them.
+
+mod dynamo;
+mod memory;
+
+pub use dynamo::DynamoUserStore;
+pub use memory::MemoryUserStore;
+
+use async_session::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// Where users and their identity links are kept
+#[async_trait]
+pub trait UserStore: std::fmt::Debug + Clone + Send + Sync + 'static {
+    async fn get_user(&self, id: &str) -> Result<Option<UserRecord>>;
+
+    async fn put_user(&self, user: &UserRecord) -> Result<()>;
+
+    /// The id of the user an identity such as `github:1234` is linked to
+    async fn find_identity(&self, identity: &str) -> Result<Option<String>>;
+
+    /// Link an identity to a user, `false` if it already is linked to someone
+    async fn link_identity(&self, identity: &str, user_id: &str) -> Result<bool>;
+
+    async fn unlink_identity(&self, identity: &str) -> Result<()>;
+
+    /// The id of the user that first signed in with this verified email
+    async fn find_email(&self, email: &str) -> Result<Option<String>>;
+
+    /// Remember a verified email for a user, `false` if another user has it
+    async fn link_email(&self, email: &str, user_id: &str) -> Result<bool>;
+
+    async fn unlink_email(&self, email: &str) -> Result<()>;
+}
+
+/// How a sign in with an identity we have not seen finds its user
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccountLinking {
+    /// Only by signing in with it while signed in as the user
+    #[default]
+    Explicit,
+    /// Also to the user with the same verified email
+    VerifiedEmail,
+}
+
+impl AccountLinking {
+    /// `ACCOUNT_LINKING=email` links by verified email, anything else only explicitly
+    pub fn from_env() -> Self {
+        match std::env::var("ACCOUNT_LINKING").as_deref() {
+            Ok("email") => AccountLinking::VerifiedEmail,
+            _ => AccountLinking::Explicit,
+        }
+    }
+}
+
+/// A user as it is stored, `PK=u#<id>` and `SK=USER` in DynamoDB
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserRecord {
+    pub id: String,
+    pub email: Option<String>,
+    pub name: Option<String>,
+    pub avatar_url: Option<String>,
+    /// Unix seconds
+    pub created_at: i64,
+    /// Unix seconds
+    pub last_login_at: i64,
+    #[serde(default)]
+    pub identities: Vec<LinkedIdentity>,
+}
+
+/// A provider account linked to a user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkedIdentity {
+    pub provider: String,
+    /// The provider's stable id for the account, e.g. GitHub's numeric user id
+    pub subject: String,
+    pub login: Option<String>,
+    pub email: Option<String>,
+    /// Unix seconds
+    pub linked_at: i64,
+}
+
+impl LinkedIdentity {
+    /// `<provider>:<subject>`, e.g. `github:1234`
+    pub fn key(&self) -> String {
+        identity_key(&self.provider, &self.subject)
+    }
+}
+
+/// What a provider tells us about the account that signed in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProviderProfile {
+    pub provider: String,
+    pub subject: String,
+    pub login: Option<String>,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    /// The provider verified that `email` belongs to the account
+    pub email_verified: bool,
+    pub avatar_url: Option<String>,
+}
+
+impl ProviderProfile {
+    pub fn identity(&self) -> String {
+        identity_key(&self.provider, &self.subject)
+    }
+}
+
+pub fn identity_key(provider: &str, subject: &str) -> String {
+    format!("{provider}:{subject}")
+}
+
+fn email_key(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+impl UserRecord {
+    pub fn new(id: &str, now: i64) -> Self {
+        Self {
+            id: id.to_owned(),
+            email: None,
+            name: None,
+            avatar_url: None,
+            created_at: now,
+            last_login_at: now,
+            identities: vec![],
+        }
+    }
+
+    /// Take the latest profile details and record the login, linking the identity if needed
+    fn record_login(&mut self, profile: &ProviderProfile, now: i64) {
+        self.last_login_at = now;
+        self.email = profile.email.clone().or(self.email.take());
+        self.name = profile.name.clone().or(self.name.take());
+        self.avatar_url = profile.avatar_url.clone().or(self.avatar_url.take());
+
+        let key = profile.identity();
+        match self.identities.iter_mut().find(|identity| identity.key() == key) {
+            Some(identity) => {
+                identity.login = profile.login.clone();
+                identity.email = profile.email.clone();
+            }
+            None => self.identities.push(LinkedIdentity {
+                provider: profile.provider.clone(),
+                subject: profile.subject.clone(),
+                login: profile.login.clone(),
+                email: profile.email.clone(),
+                linked_at: now,
+            }),
+        }
+    }
+}
+
+/// Find or create the user for a provider login and record it.
+///
+/// `signed_in` is the user of the session the login started from, if any; an identity
+/// nobody has yet is linked to them. An identity linked to someone else is refused.
+pub async fn sign_in(
+    store: &impl UserStore,
+    profile: &ProviderProfile,
+    signed_in: Option<&str>,
+    linking: AccountLinking,
+) -> Result<UserRecord> {
+    let identity = profile.identity();
+    let now = crate::session::now();
+    let verified_email = profile
+        .email
+        .as_deref()
+        .filter(|_| profile.email_verified)
+        .map(email_key);
+
+    // never switch a signed in session to whoever has the identity
+    let linked_elsewhere = |user_id: &str| signed_in.is_some_and(|signed_in| signed_in != user_id);
+    let already_linked = || {
+        Error::Forbidden(format!(
+            "This {} account is already linked to another user.",
+            profile.provider
+        ))
+    };
+
+    let user_id = match store.find_identity(&identity).await? {
+        Some(user_id) if linked_elsewhere(&user_id) => return Err(already_linked()),
+        Some(user_id) => user_id,
+        None => {
+            let by_email = match (&verified_email, linking, signed_in) {
+                (Some(email), AccountLinking::VerifiedEmail, None) => {
+                    store.find_email(email).await?
+                }
+                _ => None,
+            };
+            let user_id = signed_in
+                .map(str::to_owned)
+                .or(by_email)
+                .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+            match store.link_identity(&identity, &user_id).await? {
+                true => {
+                    tracing::info!("linked `{}` to user `{}`", identity, user_id);
+                    user_id
+                }
+                // a concurrent first login linked it first
+                false => match store.find_identity(&identity).await? {
+                    Some(user_id) if linked_elsewhere(&user_id) => return Err(already_linked()),
+                    Some(user_id) => user_id,
+                    None => {
+                        return Err(Error::storage(format!(
+                            "identity `{identity}` lost its link"
+                        )))
+                    }
+                },
+            }
+        }
+    };
+
+    let mut user = store
+        .get_user(&user_id)
+        .await?
+        .unwrap_or_else(|| UserRecord::new(&user_id, now));
+    user.record_login(profile, now);
+    store.put_user(&user).await?;
+    if let Some(email) = verified_email {
+        store.link_email(&email, &user.id).await?;
+    }
+    Ok(user)
+}
+
+/// Remove an identity from a user, who must keep at least one to sign in with. Its email
+/// stops linking to the user too, or signing in with it would link it right back.
+pub async fn unlink(store: &impl UserStore, user_id: &str, identity: &str) -> Result<UserRecord> {
+    let mut user = store.get_user(user_id).await?.ok_or(Error::NotFound)?;
+    let Some(unlinked) = user.identities.iter().find(|linked| linked.key() == identity) else {
+        return Err(Error::NotFound);
+    };
+    let email = unlinked.email.as_deref().map(email_key);
+    if user.identities.len() == 1 {
+        return Err(Error::BadRequest(String::from(
+            "You cannot unlink the only account you sign in with.",
+        )));
+    }
+
+    user.identities.retain(|linked| linked.key() != identity);
+    // a failure after this leaves a link the next sign in with it records again
+    store.put_user(&user).await?;
+    store.unlink_identity(identity).await?;
+    if let Some(email) = email {
+        if store.find_email(&email).await?.as_deref() == Some(user_id) {
+            store.unlink_email(&email).await?;
+        }
+    }
+    tracing::info!("unlinked `{}` from user `{}`", identity, user_id);
+    Ok(user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    fn profile(login: &str, email: &str) -> ProviderProfile {
+        ProviderProfile {
+            provider: String::from("github"),
+            subject: String::from("1234"),
+            login: Some(login.to_owned()),
+            name: None,
+            email: Some(email.to_owned()),
+            email_verified: true,
+            avatar_url: Some(String::from("https://avatars.example.com/1234")),
+        }
+    }
+
+    fn google(subject: &str, email: &str) -> ProviderProfile {
+        ProviderProfile {
+            provider: String::from("google"),
+            subject: subject.to_owned(),
+            login: None,
+            name: None,
+            email: Some(email.to_owned()),
+            email_verified: true,
+            avatar_url: None,
+        }
+    }
+
+    async fn explicit(store: &impl UserStore, profile: &ProviderProfile) -> Result<UserRecord> {
+        sign_in(store, profile, None, AccountLinking::Explicit).await
+    }
+
+    pub(crate) async fn conformance(store: &impl UserStore) {
+        let user = explicit(store, &profile("octo", "old@example.com")).await.unwrap();
+        assert_eq!(user.email.as_deref(), Some("old@example.com"));
+        assert_eq!(user.identities.len(), 1);
+        assert_eq!(user.identities[0].key(), "github:1234");
+
+        // the email changed at GitHub, the numeric id did not
+        let again = explicit(store, &profile("octocat", "new@example.com")).await.unwrap();
+        assert_eq!(again.id, user.id);
+        assert_eq!(again.created_at, user.created_at);
+        assert_eq!(again.email.as_deref(), Some("new@example.com"));
+        assert_eq!(again.identities.len(), 1);
+        assert_eq!(again.identities[0].login.as_deref(), Some("octocat"));
+        assert_eq!(store.get_user(&user.id).await.unwrap(), Some(again));
+
+        assert!(!store.link_identity("github:1234", "someone-else").await.unwrap());
+        assert_eq!(
+            store.find_identity("github:1234").await.unwrap().as_deref(),
+            Some(user.id.as_str())
+        );
+        assert!(store.find_identity("github:9").await.unwrap().is_none());
+
+        assert!(!store.link_email("new@example.com", "someone-else").await.unwrap());
+        assert_eq!(
+            store.find_email("new@example.com").await.unwrap().as_deref(),
+            Some(user.id.as_str())
+        );
+        store.unlink_identity("github:1234").await.unwrap();
+        assert!(store.find_identity("github:1234").await.unwrap().is_none());
+        store.unlink_email("new@example.com").await.unwrap();
+        assert!(store.find_email("new@example.com").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn unlinked_identities_sign_in_as_someone_new() {
+        let store = MemoryUserStore::new();
+        let linking = AccountLinking::VerifiedEmail;
+        let user = sign_in(&store, &profile("octo", "a@example.com"), None, linking)
+            .await
+            .unwrap();
+        let signed_in = Some(user.id.as_str());
+        sign_in(&store, &google("g1", "b@example.com"), signed_in, linking)
+            .await
+            .unwrap();
+
+        unlink(&store, &user.id, "github:1234").await.unwrap();
+        assert!(store.find_email("a@example.com").await.unwrap().is_none());
+        let again = sign_in(&store, &profile("octo", "a@example.com"), None, linking)
+            .await
+            .unwrap();
+        assert_ne!(again.id, user.id);
+        assert_eq!(again.identities.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn links_explicitly() {
+        let store = MemoryUserStore::new();
+        let user = explicit(&store, &profile("octo", "a@example.com")).await.unwrap();
+
+        // same email, but linking by email is off
+        let other = explicit(&store, &google("g1", "a@example.com")).await.unwrap();
+        assert_ne!(other.id, user.id);
+
+        let linked = sign_in(
+            &store,
+            &google("g2", "b@example.com"),
+            Some(&user.id),
+            AccountLinking::Explicit,
+        )
+        .await
+        .unwrap();
+        assert_eq!(linked.id, user.id);
+        assert_eq!(linked.identities.len(), 2);
+
+        let err = sign_in(
+            &store,
+            &google("g1", "a@example.com"),
+            Some(&user.id),
+            AccountLinking::Explicit,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::Forbidden(_)));
+    }
+
+    #[tokio::test]
+    async fn links_by_verified_email() {
+        let store = MemoryUserStore::new();
+        let user = explicit(&store, &profile("octo", "A@example.com")).await.unwrap();
+
+        let mut unverified = google("g1", "a@example.com");
+        unverified.email_verified = false;
+        let other = sign_in(&store, &unverified, None, AccountLinking::VerifiedEmail)
+            .await
+            .unwrap();
+        assert_ne!(other.id, user.id);
+
+        let linked = sign_in(
+            &store,
+            &google("g2", "a@example.com"),
+            None,
+            AccountLinking::VerifiedEmail,
+        )
+        .await
+        .unwrap();
+        assert_eq!(linked.id, user.id);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_last_identity() {
+        let store = MemoryUserStore::new();
+        let user = explicit(&store, &profile("octo", "a@example.com")).await.unwrap();
+        let err = unlink(&store, &user.id, "github:1234").await.unwrap_err();
+        assert!(matches!(err, Error::BadRequest(_)));
+        let err = unlink(&store, &user.id, "google:g1").await.unwrap_err();
+        assert!(matches!(err, Error::NotFound));
+
+        let explicit_link = Some(user.id.as_str());
+        sign_in(&store, &google("g1", "a@example.com"), explicit_link, AccountLinking::Explicit)
+            .await
+            .unwrap();
+        let user = unlink(&store, &user.id, "github:1234").await.unwrap();
+        assert_eq!(user.identities.len(), 1);
+        assert_eq!(store.get_user(&user.id).await.unwrap(), Some(user));
+        assert!(store.find_identity("github:1234").await.unwrap().is_none());
+    }
+
+    /// Misses the identity once, like a sign in racing another one that links it
+    #[derive(Debug, Clone)]
+    struct Racing {
+        inner: MemoryUserStore,
+        missed: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl UserStore for Racing {
+        async fn get_user(&self, id: &str) -> Result<Option<UserRecord>> {
+            self.inner.get_user(id).await
+        }
+
+        async fn put_user(&self, user: &UserRecord) -> Result<()> {
+            self.inner.put_user(user).await
+        }
+
+        async fn find_identity(&self, identity: &str) -> Result<Option<String>> {
+            match self.missed.swap(true, Ordering::SeqCst) {
+                false => Ok(None),
+                true => self.inner.find_identity(identity).await,
+            }
+        }
+
+        async fn link_identity(&self, identity: &str, user_id: &str) -> Result<bool> {
+            self.inner.link_identity(identity, user_id).await
+        }
+
+        async fn unlink_identity(&self, identity: &str) -> Result<()> {
+            self.inner.unlink_identity(identity).await
+        }
+
+        async fn find_email(&self, email: &str) -> Result<Option<String>> {
+            self.inner.find_email(email).await
+        }
+
+        async fn link_email(&self, email: &str, user_id: &str) -> Result<bool> {
+            self.inner.link_email(email, user_id).await
+        }
+
+        async fn unlink_email(&self, email: &str) -> Result<()> {
+            self.inner.unlink_email(email).await
+        }
+    }
+
+    #[tokio::test]
+    async fn refuses_identities_linked_by_a_racing_sign_in() {
+        let store = Racing {
+            inner: MemoryUserStore::new(),
+            missed: Arc::new(AtomicBool::new(true)),
+        };
+        let owner = explicit(&store, &profile("octo", "a@example.com")).await.unwrap();
+        let victim = explicit(&store, &google("g1", "b@example.com")).await.unwrap();
+
+        store.missed.store(false, Ordering::SeqCst);
+        let err = sign_in(
+            &store,
+            &profile("octo", "a@example.com"),
+            Some(&victim.id),
+            AccountLinking::Explicit,
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, Error::Forbidden(_)));
+
+        // signed out, the race resolves to the identity's user
+        store.missed.store(false, Ordering::SeqCst);
+        let user = explicit(&store, &profile("octo", "a@example.com")).await.unwrap();
+        assert_eq!(user.id, owner.id);
+    }
+
+    #[tokio::test]
+    async fn memory_conformance() {
+        conformance(&MemoryUserStore::new()).await;
+    }
+}
@@ -0,0 +1,236 @@
+use async_session::SessionStore;
+use lib::{
+    base_url::BaseUrl,
+    error::Error as LibError,
+    helpers::{find_cookie, Cookie, ResponseBuilder},
+    model::{User, COOKIE_NAME},
+    oauth::github::{oauth_callback, oauth_redirect, CallbackContext, GithubProvider},
+    router::{HandlerResult, Route, RouteRequest, Router},
+    secrets::SecretProvider,
+    session::session_user,
+    signin::SignInPolicy,
+    tokens::TokenVault,
+    templates::{render, Branding, LoggedOutPage, LoginPage, ProviderLink},
+    users::{self, identity_key, UserRecord, UserStore},
+};
+use serde_json::json;
+
+pub struct State<S, U> {
+    /// The GitHub instances users sign in with, by provider name
+    pub providers: Vec<GithubProvider>,
+    pub secrets: SecretProvider,
+    pub session_store: S,
+    pub user_store: U,
+    pub sign_in: SignInPolicy,
+    pub tokens: TokenVault,
+}
+
+impl<S, U> State<S, U> {
+    fn provider(&self, name: &str) -> Result<&GithubProvider, LibError> {
+        self.providers
+            .iter()
+            .find(|provider| provider.name == name)
+            .ok_or(LibError::NotFound)
+    }
+}
+
+/// The login, callback and logout routes, and the signed in user's linked identities
+pub fn router<S: SessionStore, U: UserStore>(state: State<S, U>) -> Router<State<S, U>> {
+    Router::new(state)
+        .base_url(BaseUrl::from_env())
+        .route(Route::get("/login", login_page).name("login"))
+        .route(Route::post("/logout", logout))
+        .route(Route::get("/login/{provider}/start", login_start).name("login_start"))
+        .route(Route::get("/login/{provider}/callback", login_callback).name("login_callback"))
+        .route(Route::get("/account/identities", list_identities))
+        .route(Route::delete(
+            "/account/identities/{provider}/{subject}",
+            unlink_identity,
+        ))
+}
+
+async fn login_start<S: SessionStore, U: UserStore>(req: RouteRequest<State<S, U>>) -> HandlerResult {
+    let provider = req.state.provider(&req.param::<String>("provider")?)?;
+    let callback_url = req.url_for("login_callback", &[("provider", &provider.name)])?;
+    oauth_redirect(provider, &req.state.secrets, &callback_url).await
+}
+
+async fn login_callback<S: SessionStore, U: UserStore>(req: RouteRequest<State<S, U>>) -> HandlerResult {
+    let state = &req.state;
+    oauth_callback(
+        state.provider(&req.param::<String>("provider")?)?,
+        &CallbackContext {
+            secrets: &state.secrets,
+            session_store: &state.session_store,
+            user_store: &state.user_store,
+            sign_in: &state.sign_in,
+            tokens: &state.tokens,
+        },
+        &req.request,
+        &req.url("/protected")?,
+        &req.url_for("login", &[])?,
+    )
+    .await
+}
+
+async fn login_page<S: SessionStore, U: UserStore>(req: RouteRequest<State<S, U>>) -> HandlerResult {
+    let page = render(&LoginPage {
+        branding: Branding::global(),
+        providers: req
+            .state
+            .providers
+            .iter()
+            .map(|provider| {
+                Ok(ProviderLink {
+                    label: provider.label.clone(),
+                    start_url: req.url_for("login_start", &[("provider", &provider.name)])?,
+                })
+            })
+            .collect::<Result<_, LibError>>()?,
+    })?;
+    ResponseBuilder::ok().html(page).build()
+}
+
+async fn logout<S: SessionStore, U: UserStore>(req: RouteRequest<State<S, U>>) -> HandlerResult {
+    let cookies = req.request.cookies.as_deref().unwrap_or_default();
+    if let Some(cookie) = find_cookie(cookies, COOKIE_NAME) {
+        let session_store = &req.state.session_store;
+        if let Some(session) = session_store.load_session(cookie.to_string()).await? {
+            // every session of the identity loses its token, those of the user's other
+            // identities keep theirs
+            if let Some(user) = session.get::<User>("user") {
+                match user.identity.as_ref().or(user.grant.as_ref()) {
+                    Some(identity) => req.state.tokens.delete(&user.id, identity).await?,
+                    // signed in before sessions recorded their identity
+                    None => req.state.tokens.delete_all(&user.id).await?,
+                }
+            }
+            session_store.destroy_session(session).await?;
+        }
+    }
+
+    let page = render(&LoggedOutPage {
+        branding: Branding::global(),
+        login_url: req.url_for("login", &[])?,
+    })?;
+    ResponseBuilder::ok()
+        .set_cookie(&Cookie::removal(COOKIE_NAME))
+        .html(page)
+        .build()
+}
+
+/// The id of the user signed in with the request's session cookie
+async fn signed_in_user<S: SessionStore, U: UserStore>(
+    req: &RouteRequest<State<S, U>>,
+) -> Result<String, LibError> {
+    let cookies = req.request.cookies.as_deref().unwrap_or_default();
+    let session = match find_cookie(cookies, COOKIE_NAME) {
+        Some(cookie) => req.state.session_store.load_session(cookie.to_string()).await?,
+        None => None,
+    };
+    session
+        .and_then(|session| session_user(&session))
+        .ok_or(LibError::Unauthorized(String::from(
+            "You need to sign in to manage your accounts.",
+        )))
+}
+
+fn identities_response(user: &UserRecord) -> HandlerResult {
+    ResponseBuilder::ok()
+        .json(&json!({ "user_id": user.id, "identities": user.identities }))
+        .build()
+}
+
+async fn list_identities<S: SessionStore, U: UserStore>(
+    req: RouteRequest<State<S, U>>,
+) -> HandlerResult {
+    let user_id = signed_in_user(&req).await?;
+    let user = req
+        .state
+        .user_store
+        .get_user(&user_id)
+        .await?
+        .ok_or(LibError::NotFound)?;
+    identities_response(&user)
+}
+
+/// Unlink one of the signed in user's identities, never the last one
+async fn unlink_identity<S: SessionStore, U: UserStore>(
+    req: RouteRequest<State<S, U>>,
+) -> HandlerResult {
+    let user_id = signed_in_user(&req).await?;
+    let identity = identity_key(
+        &req.param::<String>("provider")?,
+        &req.param::<String>("subject")?,
+    );
+    let user = users::unlink(&req.state.user_store, &user_id, &identity).await?;
+    req.state.tokens.delete(&user_id, &identity).await?;
+    identities_response(&user)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_session::Session;
+    use aws_lambda_events::{apigw::ApiGatewayV2httpRequest as Request, http::Method};
+    use lambda_runtime::{Context, LambdaEvent};
+    use lib::{session::MemorySessionStore, tokens::ProviderToken, users::MemoryUserStore};
+    use std::collections::HashMap;
+
+    fn token(subject: &str) -> ProviderToken {
+        ProviderToken {
+            provider: String::from("github"),
+            subject: subject.to_owned(),
+            access_token: format!("gho_{subject}"),
+            refresh_token: None,
+            expires_at: None,
+            scopes: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn logout_deletes_the_token_of_the_signed_in_identity() {
+        let session_store = MemorySessionStore::new();
+        let tokens = TokenVault::memory();
+        let router = router(State {
+            providers: vec![],
+            secrets: SecretProvider::Static(HashMap::new()),
+            session_store: session_store.clone(),
+            user_store: MemoryUserStore::new(),
+            sign_in: SignInPolicy::default(),
+            tokens: tokens.clone(),
+        });
+        tokens.put("u1", &token("1")).await.unwrap();
+        tokens.put("u1", &token("2")).await.unwrap();
+
+        // as the callback signs in without `SESSION_FOLLOWS_GRANT`
+        let mut session = Session::new();
+        session
+            .insert(
+                "user",
+                User {
+                    id: String::from("u1"),
+                    identity: Some(token("1").identity()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        let cookie = session_store.store_session(session).await.unwrap().unwrap();
+
+        let mut request = Request {
+            raw_path: Some(String::from("/logout")),
+            cookies: Some(vec![format!("{COOKIE_NAME}={cookie}")]),
+            ..Default::default()
+        };
+        request.request_context.http.method = Method::POST;
+        request.request_context.domain_name = Some(String::from("auth.example.com"));
+        let res = router
+            .handle(LambdaEvent::new(request, Context::default()))
+            .await;
+        assert_eq!(res.status_code, 200);
+
+        assert!(!tokens.has("u1", "github:1").await.unwrap());
+        assert!(tokens.has("u1", "github:2").await.unwrap());
+        assert!(session_store.load_session(cookie).await.unwrap().is_none());
+    }
+}
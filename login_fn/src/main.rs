@@ -3,11 +3,11 @@ use aws_lambda_events::apigw::{
 };
 use lambda_runtime::{run, service_fn, Error, LambdaEvent};
 use lib::{
-    aws::dynamodb::DbClient,
-    error::CustomError,
-    oauth::github::{oauth_callback, oauth_redirect},
-    session::DynamoSessionStore,
+    aws::dynamodb::DbClient, error::Error as LibError, oauth::github::GithubProvider,
+    secrets::SecretProvider, session::ConfiguredSessionStore, signin::SignInPolicy,
+    tokens::TokenVault, users::DynamoUserStore,
 };
+use login_fn::{router, State};
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
@@ -20,46 +20,28 @@ async fn main() -> Result<(), Error> {
         .user_agent("oath")
         .build()
         .map_err(Box::new)?;
-    let ssm_client = lib::aws::ssm::create_client().await;
+    let secrets = SecretProvider::ssm().await;
+    let session_store = ConfiguredSessionStore::from_env(&secrets).await?;
     let Ok(table_name) = std::env::var("TABLE_NAME") else {
-        return Err(CustomError::new("ENV VAR TABLE_NAME no set").into())
+        return Err(LibError::config("ENV VAR TABLE_NAME not set").into());
     };
-    let db_client = DbClient::new(&table_name).await;
-    let session_store = DynamoSessionStore::new(db_client.clone()).await;
-    let rest_client_ref = &rest_client;
-    let ssm_client_ref = &ssm_client;
-    let session_store_ref = &session_store;
+    let user_store = DynamoUserStore::new(DbClient::new(&table_name).await);
 
-    let func = service_fn(move |event| async move {
-        function_handler(event, rest_client_ref, ssm_client_ref, session_store_ref).await
+    let router = router(State {
+        providers: GithubProvider::all_from_env(rest_client)?,
+        secrets,
+        session_store,
+        user_store,
+        sign_in: SignInPolicy::from_env().await?,
+        tokens: TokenVault::from_env().await?,
+    });
+    let router_ref = &router;
+
+    let func = service_fn(move |event: LambdaEvent<Request>| async move {
+        Ok::<Response, Error>(router_ref.handle(event).await)
     });
 
     run(func).await?;
 
     Ok(())
 }
-
-async fn function_handler(
-    event: LambdaEvent<Request>,
-    rest_client: &reqwest::Client,
-    ssm_client: &aws_sdk_ssm::Client,
-    session_store: &DynamoSessionStore,
-) -> Result<Response, Error> {
-    let provider = &event
-        .payload
-        .path_parameters
-        .get("provider")
-        .ok_or(CustomError::new("no req param: provider"))?;
-    let action = &event
-        .payload
-        .path_parameters
-        .get("action")
-        .ok_or(CustomError::new("no req param: acition"))?;
-    match (provider.as_str(), action.as_str()) {
-        ("github", "start") => oauth_redirect(ssm_client, event).await,
-        ("github", "callback") => {
-            oauth_callback(ssm_client, rest_client, session_store, event).await
-        }
-        _ => Err(CustomError::new("unknown command")), // TODO return 404
-    }
-}